@@ -1,88 +1,197 @@
-use std::{fmt::Debug, iter::Iterator, marker::PhantomData};
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+// The tests need `std` even when the library itself is `no_std`.
+#[cfg(all(test, not(feature = "std")))]
+#[macro_use]
+extern crate std;
+
+use alloc::{collections::VecDeque, format, string::String, vec, vec::Vec};
+use core::{
+    cmp::{Ordering, Reverse},
+    error::Error,
+    fmt::{self, Debug, Display, Write},
+    hash::{Hash, Hasher},
+    iter::{self, Iterator},
+    marker::PhantomData,
+    mem,
+    ops::Range,
+    slice,
+};
 
 #[cfg(test)]
 mod tests;
 
+/// An integer type that can be used to store node indices. Trees use `usize`
+/// by default but you can use a smaller type to save memory if you don't need
+/// as many nodes.
+pub trait IndexType: Copy + Eq + Hash + Debug {
+    /// Convert from a `usize`, returning `None` if it is out of range.
+    fn from_usize(index: usize) -> Option<Self>;
+    /// Convert to a `usize`.
+    fn to_usize(self) -> usize;
+}
+
+macro_rules! impl_index_type {
+    ($($t:ty),*) => {
+        $(
+            impl IndexType for $t {
+                fn from_usize(index: usize) -> Option<Self> {
+                    index.try_into().ok()
+                }
+
+                fn to_usize(self) -> usize {
+                    self as usize
+                }
+            }
+        )*
+    };
+}
+
+impl_index_type!(u8, u16, u32, usize);
+
 /// An element in the vector. It has the user value, a level starting from 0
 /// for the root element, and a parent. The root's parent is 0.
-pub struct Node<K, V> {
+pub struct Node<K, V, I = usize> {
     /// The stored value
     pub value: V,
     /// Index of the parent node.
-    parent: usize,
+    parent: I,
     /// The number of descendents, not including this node. This allows
     /// fast iteration of children.
-    num_descendants: usize,
+    num_descendants: I,
+    /// The number of immediate children.
+    num_children: I,
     /// This just exists because we didn't use K, but we want it to be part
     /// of the type.
     _key_type: PhantomData<K>,
 }
 
-impl<K, V> Node<K, V>
+impl<K, V, I: IndexType> Node<K, V, I> {
+    /// Create a node with no descendants.
+    fn new(value: V, parent: usize) -> Self {
+        Self {
+            value,
+            parent: to_index(parent),
+            num_descendants: to_index(0),
+            num_children: to_index(0),
+            _key_type: PhantomData,
+        }
+    }
+
+    /// The number of descendents, not including this node.
+    pub fn num_descendants(&self) -> usize {
+        self.num_descendants.to_usize()
+    }
+
+    /// The number of immediate children. This is stored so it is O(1).
+    pub fn num_children(&self) -> usize {
+        self.num_children.to_usize()
+    }
+
+    /// Index of the parent node.
+    fn parent_index(&self) -> usize {
+        self.parent.to_usize()
+    }
+
+    fn set_parent(&mut self, parent: usize) {
+        self.parent = to_index(parent);
+    }
+
+    fn set_num_descendants(&mut self, num_descendants: usize) {
+        self.num_descendants = to_index(num_descendants);
+    }
+
+    fn set_num_children(&mut self, num_children: usize) {
+        self.num_children = to_index(num_children);
+    }
+}
+
+impl<K, V, I: IndexType> Node<K, V, I>
 where
     usize: Into<K>,
 {
     /// Get the ID of the parent node. The ID of the root node is equal to
     /// the root ID (so check for loops!).
     pub fn parent(&self) -> K {
-        self.parent.into()
-    }
-
-    /// The number of descendents, not including this node.
-    pub fn num_descendants(&self) -> usize {
-        self.num_descendants
+        self.parent_index().into()
     }
 }
 
-impl<K, V: Debug> Debug for Node<K, V> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl<K, V: Debug, I: IndexType> Debug for Node<K, V, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Node")
             .field("value", &self.value)
             .field("parent", &self.parent)
             .field("num_descendants", &self.num_descendants)
+            .field("num_children", &self.num_children)
             .finish()
     }
 }
 
-impl<K, V: PartialEq> PartialEq for Node<K, V> {
+impl<K, V: PartialEq, I: IndexType> PartialEq for Node<K, V, I> {
     fn eq(&self, other: &Self) -> bool {
         self.value == other.value
             && self.parent == other.parent
             && self.num_descendants == other.num_descendants
+            && self.num_children == other.num_children
+    }
+}
+
+impl<K, V: Hash, I: IndexType> Hash for Node<K, V, I> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.hash(state);
+        self.parent.hash(state);
+        self.num_descendants.hash(state);
+        self.num_children.hash(state);
     }
 }
 
-impl<K, V: Clone> Clone for Node<K, V> {
+impl<K, V: Clone, I: IndexType> Clone for Node<K, V, I> {
     fn clone(&self) -> Self {
         Self {
             value: self.value.clone(),
-            parent: self.parent.clone(),
-            num_descendants: self.num_descendants.clone(),
+            parent: self.parent,
+            num_descendants: self.num_descendants,
+            num_children: self.num_children,
             _key_type: PhantomData,
         }
     }
 }
 
-impl<K, V: Eq> Eq for Node<K, V> {}
+impl<K, V: Eq, I: IndexType> Eq for Node<K, V, I> {}
 
-impl<K, V: Copy> Copy for Node<K, V> {}
+impl<K, V: Copy, I: IndexType> Copy for Node<K, V, I> {}
+
+/// Convert a node index to the storage type.
+///
+/// # Panics
+///
+/// Panics if the index is too large for the index type.
+fn to_index<I: IndexType>(index: usize) -> I {
+    I::from_usize(index).expect("too many nodes for the tree's index type")
+}
 
 /// A flattened tree. The nodes are stored in pre-order (depth first order).
-pub struct Tree<K, V> {
-    nodes: Vec<Node<K, V>>,
+pub struct Tree<K, V, I = usize> {
+    nodes: Vec<Node<K, V, I>>,
     parent_stack: Vec<usize>,
+    /// Whether `push()` always adds to the first root rather than starting a
+    /// new one. See `with_root()`.
+    single_root: bool,
 }
 
-impl<K, V> Default for Tree<K, V> {
+impl<K, V, I: IndexType> Default for Tree<K, V, I> {
     fn default() -> Self {
         Self {
             nodes: Default::default(),
             parent_stack: Default::default(),
+            single_root: false,
         }
     }
 }
 
-impl<K, V> Tree<K, V>
+impl<K, V, I: IndexType> Tree<K, V, I>
 where
     usize: Into<K>,
     K: Into<usize>,
@@ -97,7 +206,168 @@ where
         Self {
             nodes: Vec::with_capacity(capacity),
             parent_stack: Vec::new(),
+            single_root: false,
+        }
+    }
+
+    /// Create a tree containing a single root node, which is the current node
+    /// so that `push()` adds children to it.
+    pub fn leaf(value: V) -> Self {
+        let mut tree = Self::new();
+        tree.push(value);
+        tree
+    }
+
+    /// Create a tree containing a single root node that can't have siblings.
+    /// Calling `up()` at the root does nothing, and if there is no current node
+    /// (e.g. after `finalize()`) then `push()` adds a child of the root rather
+    /// than a new root. Operations that add roots explicitly, such as
+    /// `apply_edits()`, can still produce multiple roots. This setting isn't
+    /// included when comparing or hashing trees.
+    pub fn with_root(value: V) -> Self {
+        let mut tree = Self::leaf(value);
+        tree.single_root = true;
+        tree
+    }
+
+    /// Create a `TreeBuilder` for building a tree in pre-order.
+    pub fn builder() -> TreeBuilder<K, V, I> {
+        TreeBuilder::new()
+    }
+
+    /// Combine several trees into one tree with multiple roots. The trees'
+    /// nodes are added one after another, in order.
+    pub fn concat(trees: impl IntoIterator<Item = Tree<K, V, I>>) -> Self {
+        let mut tree = Self::new();
+        for other in trees {
+            tree.insert_block(None, tree.len(), other.nodes);
+        }
+        tree
+    }
+
+    /// Flatten a recursive `Nested` tree.
+    pub fn from_nested(root: Nested<V>) -> Self {
+        Self::from_nested_forest(vec![root])
+    }
+
+    /// Flatten several recursive `Nested` trees into one tree with a root for
+    /// each of them.
+    pub fn from_nested_forest(roots: Vec<Nested<V>>) -> Self {
+        let mut tree = Self::new();
+        for root in roots {
+            tree.push_nested(root);
+        }
+        tree.recompute_descendant_counts();
+        tree
+    }
+
+    /// Build a tree from a list of `(parent, child)` edges between node
+    /// indices, where `values[i]` is the value of node `i`. The nodes are laid
+    /// out by a depth-first search starting from each of `roots` in order,
+    /// with children in the same order as their edges.
+    ///
+    /// Returns an error if any index is out of range, if a node has more than
+    /// one parent (or is a root and has a parent), or if a node can't be
+    /// reached from the roots.
+    pub fn from_edges(
+        edges: &[(usize, usize)],
+        values: Vec<V>,
+        roots: &[usize],
+    ) -> Result<Self, BuildError> {
+        let len = values.len();
+        let mut parents = vec![None; len];
+        let mut children = vec![Vec::new(); len];
+        // Roots are their own parents.
+        for (parent, child) in edges
+            .iter()
+            .copied()
+            .chain(roots.iter().map(|&root| (root, root)))
+        {
+            if let Some(id) = [parent, child].into_iter().find(|&id| id >= len) {
+                return Err(BuildError::InvalidId { id });
+            }
+            if parents[child].replace(parent).is_some() {
+                return Err(BuildError::MultipleParents { id: child });
+            }
+            if parent != child {
+                children[parent].push(child);
+            }
+        }
+
+        let mut values: Vec<Option<V>> = values.into_iter().map(Some).collect();
+        let mut tree = Self::with_capacity(len);
+        for &root in roots {
+            tree.push(values[root].take().unwrap());
+            // Each open node and the index of its next child.
+            let mut stack = vec![(root, 0)];
+            while let Some((id, next)) = stack.last_mut() {
+                match children[*id].get(*next) {
+                    Some(&child) => {
+                        *next += 1;
+                        tree.push(values[child].take().unwrap());
+                        stack.push((child, 0));
+                    }
+                    None => {
+                        stack.pop();
+                        tree.up();
+                    }
+                }
+            }
         }
+
+        // Every node has at most one parent, so following the parents of an
+        // unreached node can only end in a cycle or a node with no parent. If
+        // it doesn't end within `len` steps then it must be a cycle.
+        if let Some(id) = values.iter().position(Option::is_some) {
+            let mut top = id;
+            for _ in 0..len {
+                match parents[top] {
+                    Some(parent) => top = parent,
+                    None => return Err(BuildError::Disconnected { id: top }),
+                }
+            }
+            return Err(BuildError::Cycle { id });
+        }
+        Ok(tree)
+    }
+
+    /// Return the number of nodes the tree can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.nodes.capacity()
+    }
+
+    /// Reserve capacity for at least `additional` more nodes.
+    pub fn reserve(&mut self, additional: usize) {
+        self.nodes.reserve(additional);
+    }
+
+    /// Reserve capacity for grafting `subtree` into this tree, so that
+    /// `graft()` doesn't need to reallocate.
+    pub fn reserve_for_graft(&mut self, subtree: &Tree<K, V, I>) {
+        self.reserve(subtree.len());
+    }
+
+    /// Shrink the capacity of the tree (and the current node stack) as much
+    /// as possible.
+    pub fn shrink_to_fit(&mut self) {
+        self.nodes.shrink_to_fit();
+        self.parent_stack.shrink_to_fit();
+    }
+
+    /// Free the current node stack once you have finished building the tree.
+    /// This is the same as `up()`ing all the way to the top, so a later
+    /// `push()` would add a new root. Since `==` compares the current node
+    /// too, finalized trees are equal if their nodes are.
+    pub fn finalize(&mut self) {
+        self.parent_stack = Vec::new();
+    }
+
+    /// Return the number of bytes allocated on the heap by the tree itself.
+    /// This is based on capacity rather than length and doesn't include any
+    /// heap memory owned by the values.
+    pub fn memory_usage(&self) -> usize {
+        self.nodes.capacity() * mem::size_of::<Node<K, V, I>>()
+            + self.parent_stack.capacity() * mem::size_of::<usize>()
     }
 
     /// Return the total number of nodes in the tree.
@@ -118,18 +388,22 @@ where
     /// If you don't push with the correct values then iteration may give
     /// unexpected results.
     pub fn push(&mut self, value: V) -> K {
+        if self.single_root && self.parent_stack.is_empty() && !self.is_empty() {
+            self.parent_stack.push(0);
+        }
         let id = self.len();
 
-        self.nodes.push(Node {
-            value,
-            parent: *self.parent_stack.last().unwrap_or(&id),
-            num_descendants: 0,
-            _key_type: PhantomData,
-        });
+        self.nodes
+            .push(Node::new(value, *self.parent_stack.last().unwrap_or(&id)));
 
         // Increment the descendent counts of all parent nodes.
         for &parent in self.parent_stack.iter() {
-            self.nodes[parent].num_descendants += 1;
+            let parent = &mut self.nodes[parent];
+            parent.set_num_descendants(parent.num_descendants() + 1);
+        }
+        if let Some(&parent) = self.parent_stack.last() {
+            let parent = &mut self.nodes[parent];
+            parent.set_num_children(parent.num_children() + 1);
         }
 
         self.parent_stack.push(id);
@@ -137,6 +411,92 @@ where
         id.into()
     }
 
+    /// Like `push()`, but returns an error instead of panicking or corrupting
+    /// the tree if the current node stack refers to nodes that don't exist
+    /// (e.g. after nodes were removed while building), or if the new node's
+    /// ID wouldn't fit in the index type.
+    pub fn push_checked(&mut self, value: V) -> Result<K, PushError> {
+        if self.parent_stack.iter().any(|&id| id >= self.len()) {
+            return Err(PushError::InvalidCurrent);
+        }
+        if I::from_usize(self.len()).is_none() {
+            return Err(PushError::TooManyNodes);
+        }
+        Ok(self.push(value))
+    }
+
+    /// Push a node, call `build_children` to add its children, and then go
+    /// back up to the node that was current before. This means you don't have
+    /// to match up `push()` and `up()` calls yourself, e.g.
+    /// `tree.child(0, |t| { t.child(1, |t| { t.push(2); }); });`
+    ///
+    /// Returns the ID of the new node.
+    pub fn child<F: FnOnce(&mut Tree<K, V, I>)>(&mut self, value: V, build_children: F) -> K {
+        let id = self.len();
+        self.push(value);
+        build_children(self);
+        // Go back up to the node that was current before we started.
+        let depth = self.parent_stack.iter().position(|&entry| entry == id);
+        if let Some(depth) = depth {
+            self.parent_stack.truncate(depth);
+        }
+        id.into()
+    }
+
+    /// Insert a new leaf node as the first child of `parent`, i.e. immediately
+    /// after it. Returns the ID of the new node. This shifts all later nodes
+    /// along so it is O(n).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `parent` is not a valid ID.
+    pub fn prepend_child(&mut self, parent: K, value: V) -> K {
+        let parent = parent.into();
+        assert!(parent < self.len(), "invalid parent ID {parent}");
+        self.insert_block(Some(parent), parent + 1, vec![Node::new(value, 0)]);
+        (parent + 1).into()
+    }
+
+    /// Add a new leaf node as the last child of an arbitrary existing node.
+    /// Returns the ID of the new node. Unlike `push()` this doesn't require
+    /// building in pre-order. The new node is inserted after `parent`'s
+    /// existing descendants, shifting all later nodes along, so it is O(n).
+    ///
+    /// This doesn't change the current node unless `parent` is one of its
+    /// ancestors, in which case the new node goes after the current node's
+    /// subtree and `parent` becomes the current node.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `parent` is not a valid ID.
+    pub fn push_child(&mut self, parent: K, value: V) -> K {
+        let parent = parent.into();
+        assert!(parent < self.len(), "invalid parent ID {parent}");
+        let pos = self.subtree_range(parent).end;
+        self.insert_block(Some(parent), pos, vec![Node::new(value, 0)]);
+        pos.into()
+    }
+
+    /// Remove the last node and return its value, or `None` if the tree is
+    /// empty. The last node is always a leaf. If it is the current node then
+    /// its parent becomes current, so this undoes a `push()`. It doesn't undo
+    /// `up()` calls.
+    pub fn pop(&mut self) -> Option<V> {
+        let node = self.nodes.pop()?;
+        let id = self.len();
+        if node.parent_index() != id {
+            self.for_each_ancestor(node.parent_index(), |node| {
+                node.set_num_descendants(node.num_descendants() - 1)
+            });
+            let parent = &mut self.nodes[node.parent_index()];
+            parent.set_num_children(parent.num_children() - 1);
+        }
+        if self.parent_stack.last() == Some(&id) {
+            self.parent_stack.pop();
+        }
+        Some(node.value)
+    }
+
     /// Set the current node to its parent. It's safe to call this if the
     /// the tree is empty, in which case nothing will change.
     ///
@@ -145,167 +505,2394 @@ where
     ///
     /// It is ok to call this if the current node is the root node. If you then
     /// add more nodes you will end up with a tree with multiple root nodes.
-    /// this should work fine but might be confusing!
+    /// this should work fine but might be confusing! Trees created with
+    /// `with_root()` stay at the root instead.
     pub fn up(&mut self) -> Option<K> {
+        if self.single_root && self.parent_stack.len() == 1 {
+            return Some(self.parent_stack[0].into());
+        }
+        self.parent_stack.pop();
+        self.parent_stack.last().map(|&id| id.into())
+    }
+
+    /// Set the current node to its parent, and return the new current node.
+    /// Unlike `up()` this returns an error and does nothing if the current
+    /// node is a root (or the tree is empty), so it never lets you start a
+    /// new root by accident.
+    pub fn try_up(&mut self) -> Result<K, AtTopError> {
+        if self.parent_stack.len() < 2 {
+            return Err(AtTopError);
+        }
         self.parent_stack.pop();
+        Ok(self.parent_stack[self.parent_stack.len() - 1].into())
+    }
+
+    /// Get the ID of the current node, i.e. the node that `push()` will add a
+    /// child to, or `None` if `push()` will add a new root. This is not
+    /// always the same as `last()`: `last()` is the most recently pushed node
+    /// whereas `up()` moves the current node to its parent.
+    pub fn current(&self) -> Option<K> {
         self.parent_stack.last().map(|&id| id.into())
     }
 
+    /// Get a mutable reference to the current node (see `current()`).
+    pub fn current_mut(&mut self) -> Option<&mut Node<K, V, I>> {
+        let id = *self.parent_stack.last()?;
+        self.nodes.get_mut(id)
+    }
+
+    /// Remove all nodes. This keeps the allocated capacity.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.parent_stack.clear();
+    }
+
+    /// Keep the first `len` nodes (in pre-order) and remove the rest. The
+    /// descendant counts of the remaining nodes are fixed up and any removed
+    /// nodes are dropped from the current node stack, so you can carry on
+    /// pushing afterwards. Does nothing if `len` is at least `len()`.
+    pub fn truncate(&mut self, len: usize) {
+        if self.len() <= len {
+            return;
+        }
+        for id in len..self.len() {
+            let parent = self.nodes[id].parent_index();
+            if parent < len {
+                let parent = &mut self.nodes[parent];
+                parent.set_num_children(parent.num_children() - 1);
+            }
+        }
+        self.nodes.truncate(len);
+        for (id, node) in self.nodes.iter_mut().enumerate() {
+            node.set_num_descendants(node.num_descendants().min(len - 1 - id));
+        }
+        // The stack is a chain of ancestors so its IDs are increasing.
+        while self.parent_stack.last().is_some_and(|&id| id >= len) {
+            self.parent_stack.pop();
+        }
+    }
+
+    /// Cap the tree at `max_nodes` nodes by removing nodes from the end (in
+    /// pre-order). This is the same as `truncate()`.
+    pub fn trim_to_size(&mut self, max_nodes: usize) {
+        self.truncate(max_nodes);
+    }
+
     /// Get a reference to a node. Returns `None` for invalid IDs.
-    pub fn get(&self, id: K) -> Option<&Node<K, V>> {
+    pub fn get(&self, id: K) -> Option<&Node<K, V, I>> {
         self.nodes.get(id.into())
     }
 
     /// Get a mutable reference to a node. Returns `None` for invalid IDs.
-    pub fn get_mut(&mut self, id: K) -> Option<&mut Node<K, V>> {
+    pub fn get_mut(&mut self, id: K) -> Option<&mut Node<K, V, I>> {
         self.nodes.get_mut(id.into())
     }
 
+    /// Get a reference to a node's value. Returns `None` for invalid IDs.
+    pub fn get_value(&self, id: K) -> Option<&V> {
+        self.get(id).map(|node| &node.value)
+    }
+
+    /// Get a mutable reference to a node's value. Returns `None` for invalid
+    /// IDs.
+    pub fn get_value_mut(&mut self, id: K) -> Option<&mut V> {
+        self.get_mut(id).map(|node| &mut node.value)
+    }
+
+    /// Get mutable references to several nodes at once. Returns `None` if any
+    /// of the IDs are invalid or the same ID appears more than once.
+    pub fn get_many_mut<const N: usize>(&mut self, ids: [K; N]) -> Option<[&mut Node<K, V, I>; N]> {
+        self.nodes.get_disjoint_mut(ids.map(|id| id.into())).ok()
+    }
+
+    /// Swap the values of two nodes, leaving the structure of the tree
+    /// unchanged. Returns false (and does nothing) if either ID is invalid.
+    pub fn swap_values(&mut self, a: K, b: K) -> bool {
+        let a = a.into();
+        let b = b.into();
+        if a == b {
+            return a < self.len();
+        }
+        match self.nodes.get_disjoint_mut([a, b]) {
+            Ok([a, b]) => {
+                mem::swap(&mut a.value, &mut b.value);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Replace the value of a node, returning the old value. Returns `None`
+    /// (and drops `value`) if the ID is invalid.
+    pub fn replace_value(&mut self, id: K, value: V) -> Option<V> {
+        self.get_mut(id)
+            .map(|node| mem::replace(&mut node.value, value))
+    }
+
     /// Get a reference to the first node (or `None` if the tree is empty).
     /// This will normally be the tree's only root node but it is possible
     /// to have trees with multiple roots.
-    pub fn first(&self) -> Option<&Node<K, V>> {
+    pub fn first(&self) -> Option<&Node<K, V, I>> {
         self.nodes.first()
     }
 
     /// Get a mutable reference to the first node (or `None` if the tree is empty).
     /// This will normally be the tree's only root node but it is possible
     /// to have trees with multiple roots.
-    pub fn first_mut(&mut self) -> Option<&mut Node<K, V>> {
+    pub fn first_mut(&mut self) -> Option<&mut Node<K, V, I>> {
         self.nodes.first_mut()
     }
 
     /// Get a reference to the last node (or `None` if the tree is empty).
     /// This is the "current" node. If you call push() it will add a child
     /// to this node.
-    pub fn last(&self) -> Option<&Node<K, V>> {
+    pub fn last(&self) -> Option<&Node<K, V, I>> {
         self.nodes.last()
     }
 
     /// Get a mutable reference to the last node (or `None` if the tree is empty).
     /// This is the "current" node. If you call push() it will add a child
     /// to this node.
-    pub fn last_mut(&mut self) -> Option<&mut Node<K, V>> {
+    pub fn last_mut(&mut self) -> Option<&mut Node<K, V, I>> {
         self.nodes.last_mut()
     }
 
     /// Iterate through all the tree nodes in the order they were added (which
-    /// must be pre-order / depth first).
-    pub fn iter(&self) -> impl Iterator<Item = &Node<K, V>> {
+    /// must be pre-order / depth first). This can be reversed to iterate in
+    /// reverse pre-order, which visits every node after all its descendants.
+    pub fn iter(&self) -> slice::Iter<'_, Node<K, V, I>> {
         self.nodes.iter()
     }
 
+    /// Iterate through all the tree nodes and their IDs in the order they were
+    /// added (which must be pre-order / depth first).
+    pub fn iter_with_ids(&self) -> impl Iterator<Item = (K, &Node<K, V, I>)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(id, node)| (id.into(), node))
+    }
+
+    /// Iterate through all the tree nodes in pre-order along with their depth
+    /// (roots are at depth 0) and ID. The depth is tracked as it goes so this
+    /// is O(n) overall.
+    pub fn iter_depth(&self) -> impl Iterator<Item = (usize, K, &Node<K, V, I>)> {
+        self.depths(0..self.len())
+            .map(|(id, depth)| (depth, id.into(), &self.nodes[id]))
+    }
+
+    /// Iterate through the tree as a stream of events, like a SAX parser.
+    /// Each node produces an `Event::Open` in pre-order and then an
+    /// `Event::Close` after the events for all of its descendants.
+    pub fn events(&self) -> impl Iterator<Item = Event<'_, K, V, I>> {
+        let mut next = 0;
+        // The open nodes and the last index of each of their subtrees.
+        let mut open: Vec<(usize, usize)> = Vec::new();
+        iter::from_fn(move || {
+            if let Some(&(id, end)) = open.last() {
+                if next > end {
+                    open.pop();
+                    return Some(Event::Close(id.into()));
+                }
+            }
+            let node = self.nodes.get(next)?;
+            open.push((next, next + node.num_descendants()));
+            next += 1;
+            Some(Event::Open((next - 1).into(), node))
+        })
+    }
+
+    /// Iterate through all the nodes that have no children, and their IDs.
+    pub fn leaves(&self) -> impl Iterator<Item = (K, &Node<K, V, I>)> {
+        self.iter_with_ids()
+            .filter(|(_, node)| node.num_descendants() == 0)
+    }
+
+    /// Iterate through all the nodes that have at least one child, and their
+    /// IDs.
+    pub fn internal_nodes(&self) -> impl Iterator<Item = (K, &Node<K, V, I>)> {
+        self.iter_with_ids()
+            .filter(|(_, node)| node.num_descendants() > 0)
+    }
+
     /// Convert the tree into an iterator through all the tree nodes in the
     /// order they were added (which must be pre-order / depth first).
-    pub fn into_iter(self) -> impl Iterator<Item = Node<K, V>> {
+    #[allow(clippy::should_implement_trait)]
+    pub fn into_iter(self) -> impl Iterator<Item = Node<K, V, I>> {
         self.nodes.into_iter()
     }
 
+    /// Convert the tree to use a different key type. IDs are stored as
+    /// indices internally so the nodes and current node are unchanged.
+    pub fn rebase_keys<K2>(self) -> Tree<K2, V, I>
+    where
+        usize: Into<K2>,
+        K2: Into<usize>,
+    {
+        Tree {
+            nodes: self
+                .nodes
+                .into_iter()
+                .map(|node| Node {
+                    value: node.value,
+                    parent: node.parent,
+                    num_descendants: node.num_descendants,
+                    num_children: node.num_children,
+                    _key_type: PhantomData,
+                })
+                .collect(),
+            parent_stack: self.parent_stack,
+            single_root: self.single_root,
+        }
+    }
+
+    /// Create a tree with the same structure (and current node) where each
+    /// value is given by `f(id, parent, value)`. `parent` is `None` for roots.
+    pub fn map_with_ids<W, F: FnMut(K, Option<K>, &V) -> W>(&self, mut f: F) -> Tree<K, W, I> {
+        Tree {
+            nodes: self
+                .nodes
+                .iter()
+                .enumerate()
+                .map(|(id, node)| {
+                    let parent = node.parent_index();
+                    Node {
+                        value: f(
+                            id.into(),
+                            (parent != id).then(|| parent.into()),
+                            &node.value,
+                        ),
+                        parent: node.parent,
+                        num_descendants: node.num_descendants,
+                        num_children: node.num_children,
+                        _key_type: PhantomData,
+                    }
+                })
+                .collect(),
+            parent_stack: self.parent_stack.clone(),
+            single_root: self.single_root,
+        }
+    }
+
+    /// Create a tree with the same structure (and current node) where each
+    /// value is given by `f(value)`, stopping at the first error.
+    pub fn try_map<W, E, F: FnMut(&V) -> Result<W, E>>(
+        &self,
+        mut f: F,
+    ) -> Result<Tree<K, W, I>, E> {
+        Ok(Tree {
+            nodes: self
+                .nodes
+                .iter()
+                .map(|node| {
+                    Ok(Node {
+                        value: f(&node.value)?,
+                        parent: node.parent,
+                        num_descendants: node.num_descendants,
+                        num_children: node.num_children,
+                        _key_type: PhantomData,
+                    })
+                })
+                .collect::<Result<_, E>>()?,
+            parent_stack: self.parent_stack.clone(),
+            single_root: self.single_root,
+        })
+    }
+
     /// Get a slice of all nodes in the tree in the order they were added
     /// (which must be pre-order / depth-first).
-    pub fn all(&self) -> &[Node<K, V>] {
+    pub fn all(&self) -> &[Node<K, V, I>] {
         self.nodes.as_slice()
     }
 
     /// Get a slice of all the descendents of a node.
-    pub fn descendents(&self, id: K) -> &[Node<K, V>] {
+    pub fn descendents(&self, id: K) -> &[Node<K, V, I>] {
         let id = id.into();
         let num_descendants = self
             .nodes
             .get(id)
-            .map(|node| node.num_descendants)
+            .map(|node| node.num_descendants())
             .unwrap_or_default();
         &self.nodes[id + 1..id + 1 + num_descendants]
     }
 
+    /// Get a mutable slice of all the descendants of a node.
+    pub fn descendants_mut(&mut self, id: K) -> &mut [Node<K, V, I>] {
+        let id = id.into();
+        let num_descendants = self
+            .nodes
+            .get(id)
+            .map(|node| node.num_descendants())
+            .unwrap_or_default();
+        &mut self.nodes[id + 1..id + 1 + num_descendants]
+    }
+
+    /// Get an iterator over the root nodes, i.e. the nodes with no parent.
+    pub fn roots(&self) -> impl Iterator<Item = (K, &Node<K, V, I>)> {
+        let mut id = 0;
+        iter::from_fn(move || {
+            let node = self.nodes.get(id)?;
+            let root = (id.into(), node);
+            id += node.num_descendants() + 1;
+            Some(root)
+        })
+    }
+
+    /// Count the root nodes. This skips over each root's descendants so it is
+    /// O(number of roots).
+    pub fn num_roots(&self) -> usize {
+        self.roots().count()
+    }
+
+    /// Returns true if the tree has more than one root.
+    pub fn is_forest(&self) -> bool {
+        self.num_roots() > 1
+    }
+
+    /// Returns true if the tree has exactly one root.
+    pub fn is_single_tree(&self) -> bool {
+        self.num_roots() == 1
+    }
+
+    /// Iterate through the roots' IDs along with slices of the nodes in each
+    /// root's tree (the root and all its descendants).
+    pub fn iter_root_subtrees(&self) -> impl Iterator<Item = (K, &[Node<K, V, I>])> {
+        self.roots().map(|(id, node)| {
+            let start = id.into();
+            (
+                start.into(),
+                &self.nodes[start..=start + node.num_descendants()],
+            )
+        })
+    }
+
     /// Get an iterator over the parents of a node (not including the node itself).
-    pub fn parents(&self, id: K) -> ParentIter<'_, K, V> {
+    pub fn parents(&self, id: K) -> ParentIter<'_, K, V, I> {
         let id = id.into();
         ParentIter { id, tree: self }
     }
 
+    /// Get an iterator over a node and then its parents. This is the same as
+    /// `parents()` except that it includes the node itself.
+    pub fn ancestors_with_self(&self, id: K) -> impl Iterator<Item = (K, &Node<K, V, I>)> {
+        let id = id.into();
+        self.nodes
+            .get(id)
+            .map(|node| (id.into(), node))
+            .into_iter()
+            .chain(self.parents(id.into()))
+    }
+
+    /// Get the `n`th ancestor of a node, where 0 is the node itself, 1 is its
+    /// parent, 2 its grandparent and so on. Returns `None` if there aren't
+    /// that many ancestors or the ID is invalid.
+    pub fn nth_ancestor(&self, id: K, n: usize) -> Option<K> {
+        self.ancestors_with_self(id).nth(n).map(|(id, _)| id)
+    }
+
+    /// Get the ancestor of a node (or the node itself) at the given depth,
+    /// where roots are at level 0. Returns `None` if the node is shallower
+    /// than `level` or the ID is invalid.
+    pub fn ancestor_at_level(&self, id: K, level: usize) -> Option<K> {
+        let id = id.into();
+        let depth = self.depth(id.into());
+        self.nth_ancestor(id.into(), depth.checked_sub(level)?)
+    }
+
+    /// Get the path from a node up to its root, including the node itself,
+    /// i.e. `[id, parent, ..., root]`. Returns an empty `Vec` for invalid IDs.
+    pub fn path_to_root(&self, id: K) -> Vec<K> {
+        let id = id.into();
+        if id >= self.len() {
+            return Vec::new();
+        }
+        let mut path = Vec::with_capacity(self.parents(id.into()).count() + 1);
+        path.push(id.into());
+        path.extend(self.parents(id.into()).map(|(parent, _)| parent));
+        path
+    }
+
+    /// Get the ID of the root of the tree containing a node, which is the node
+    /// itself if it is a root. Returns `None` for invalid IDs.
+    pub fn root_of(&self, id: K) -> Option<K> {
+        self.ancestors_with_self(id).last().map(|(root, _)| root)
+    }
+
     /// Get an iterator over the immediate children of a node.
-    pub fn children(&self, id: K) -> ChildrenIter<'_, K, V> {
+    pub fn children(&self, id: K) -> ChildrenIter<'_, K, V, I> {
         let id = id.into();
         ChildrenIter {
+            parent_id: id,
             current_id: id + 1,
             max_id: id
                 + self
                     .nodes
                     .get(id)
-                    .map(|node| node.num_descendants)
+                    .map(|node| node.num_descendants())
                     .unwrap_or_default(),
             tree: self,
         }
     }
-}
 
-impl<K, V: Debug> Debug for Tree<K, V> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Tree")
-            .field("nodes", &self.nodes)
-            .field("parent_stack", &self.parent_stack)
-            .finish()
+    /// Get an iterator over mutable references to the immediate children of
+    /// a node.
+    pub fn children_mut(&mut self, id: K) -> ChildrenMutIter<'_, K, V, I> {
+        let range = self.subtree_range(id.into());
+        ChildrenMutIter {
+            next_id: range.start + 1,
+            rest: self
+                .nodes
+                .get_mut(range.start + 1..range.end)
+                .unwrap_or_default(),
+        }
     }
-}
 
-impl<K, V: PartialEq> PartialEq for Tree<K, V> {
-    fn eq(&self, other: &Self) -> bool {
-        self.nodes == other.nodes && self.parent_stack == other.parent_stack
+    /// Get the position of a node among its siblings, or among the roots if
+    /// it is a root. Returns `None` for invalid IDs.
+    pub fn sibling_index(&self, id: K) -> Option<usize> {
+        let id = id.into();
+        let parent = self.nodes.get(id)?.parent_index();
+        if parent == id {
+            self.roots().position(|(root, _)| root.into() == id)
+        } else {
+            self.children(parent.into())
+                .position(|(child, _)| child.into() == id)
+        }
     }
-}
 
-impl<K, V: Clone> Clone for Tree<K, V> {
-    fn clone(&self) -> Self {
-        Self {
-            nodes: self.nodes.clone(),
-            parent_stack: self.parent_stack.clone(),
-        }
+    /// Get the ID of the `n`th immediate child of a node. Earlier children's
+    /// subtrees are skipped over rather than visited. Returns `None` if there
+    /// aren't that many children or the ID is invalid.
+    pub fn nth_child(&self, id: K, n: usize) -> Option<K> {
+        self.children(id).nth(n).map(|(child, _)| child)
     }
-}
 
-impl<K, V: Eq> Eq for Tree<K, V> {}
+    /// Iterate over every parent-child edge in the tree, in pre-order of the
+    /// child, yielding `((parent_id, parent), (child_id, child))`.
+    #[allow(clippy::type_complexity)]
+    pub fn parent_child_pairs(
+        &self,
+    ) -> impl Iterator<Item = ((K, &Node<K, V, I>), (K, &Node<K, V, I>))> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(id, node)| node.parent_index() != *id)
+            .map(|(id, node)| {
+                (
+                    (node.parent_index().into(), &self.nodes[node.parent_index()]),
+                    (id.into(), node),
+                )
+            })
+    }
 
-pub struct ParentIter<'a, K, V> {
-    id: usize,
-    tree: &'a Tree<K, V>,
-}
+    /// Iterate through the `(parent, child)` ID pairs of every non-root node.
+    pub fn edges(&self) -> impl Iterator<Item = (K, K)> + '_ {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(id, node)| node.parent_index() != *id)
+            .map(|(id, node)| (node.parent_index().into(), id.into()))
+    }
 
-impl<'a, K, V> Iterator for ParentIter<'a, K, V>
-where
-    K: Into<usize>,
-    usize: Into<K>,
-{
-    type Item = (K, &'a Node<K, V>);
+    /// Get the IDs of the immediate children of every node, indexed by the
+    /// node's ID.
+    pub fn adjacency_list(&self) -> Vec<Vec<K>> {
+        let mut list: Vec<Vec<K>> = iter::repeat_with(Vec::new).take(self.len()).collect();
+        for (parent, child) in self.edges() {
+            list[parent.into()].push(child);
+        }
+        list
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.tree.nodes.get(self.id).and_then(|node| {
-            if node.parent == self.id {
-                None
-            } else {
-                self.id = node.parent;
-                self.tree
-                    .nodes
-                    .get(self.id)
-                    .map(|node| (self.id.into(), node))
+    /// Iterate over every node that has children, in pre-order, together with
+    /// the IDs of its immediate children. Leaves produce no group. Roots are
+    /// not children of anything so they are never included in a group.
+    pub fn sibling_groups(&self) -> impl Iterator<Item = (K, Vec<K>)> + '_ {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.num_descendants() > 0)
+            .map(|(id, _)| {
+                let children = self.children(id.into()).map(|(child, _)| child).collect();
+                (id.into(), children)
+            })
+    }
+
+    /// Get the lowest common ancestor of two nodes, i.e. the deepest node that
+    /// is an ancestor of both. A node counts as its own ancestor here, so
+    /// `lca(a, a)` is `a`. Returns `None` if the nodes are in different root
+    /// trees or either ID is invalid.
+    pub fn lca(&self, a: K, b: K) -> Option<K> {
+        let b = b.into();
+        if b >= self.len() {
+            return None;
+        }
+        // Walk up from `a` until we reach a node whose descendant range
+        // contains `b`.
+        let mut id = a.into();
+        loop {
+            let node = self.nodes.get(id)?;
+            if self.subtree_range(id).contains(&b) {
+                return Some(id.into());
             }
-        })
+            if node.parent_index() == id {
+                return None;
+            }
+            id = node.parent_index();
+        }
     }
-}
 
-pub struct ChildrenIter<'a, K, V> {
-    current_id: usize,
-    max_id: usize,
-    tree: &'a Tree<K, V>,
-}
+    /// Get the number of edges between two nodes, or `None` if they are in
+    /// different root trees or either ID is invalid.
+    pub fn distance(&self, a: K, b: K) -> Option<usize> {
+        let a = a.into();
+        let b = b.into();
+        let lca = self.lca(a.into(), b.into())?;
+        Some(self.depth(a.into()) + self.depth(b.into()) - 2 * self.depth(lca))
+    }
 
-impl<'a, K, V> Iterator for ChildrenIter<'a, K, V>
-where
-    usize: Into<K>,
-{
-    type Item = (K, &'a Node<K, V>);
+    /// Iterate through the ancestors shared by two nodes, from their lowest
+    /// common ancestor up to the root. As with `lca()`, a node counts as its
+    /// own ancestor. Yields nothing if the nodes are in different root trees
+    /// or either ID is invalid.
+    pub fn common_ancestors(&self, a: K, b: K) -> impl Iterator<Item = K> + '_ {
+        self.lca(a, b)
+            .into_iter()
+            .flat_map(|lca| self.ancestors_with_self(lca).map(|(id, _)| id))
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    /// Get the depth of a node, which is the number of parents it has. Roots
+    /// (and invalid IDs) have depth 0. This is O(depth).
+    pub fn depth(&self, id: K) -> usize {
+        self.parents(id).count()
+    }
+
+    /// Get the number of nodes in the subtree rooted at `id`, including the
+    /// node itself. Returns 0 for invalid IDs.
+    pub fn subtree_len(&self, id: K) -> usize {
+        self.subtree_range(id.into()).len()
+    }
+
+    /// Export the tree in Graphviz DOT format. Nodes are named `n0`, `n1`, etc.
+    /// and labelled using `label`. There is an edge from each node to its
+    /// parent (roots have none).
+    pub fn to_dot<F: Fn(&V) -> String>(&self, label: F) -> String {
+        let mut dot = String::from("digraph {\n");
+        for (id, node) in self.nodes.iter().enumerate() {
+            let label = label(&node.value)
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"");
+            writeln!(dot, "    n{id} [label=\"{label}\"];").unwrap();
+            if node.parent_index() != id {
+                writeln!(dot, "    n{id} -> n{};", node.parent_index()).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Render the tree as an indented outline with one node per line,
+    /// prefixed by `indent` once per level of depth. Roots are not indented.
+    /// This is the inverse of `Tree::from_indented()` as long as the labels
+    /// don't contain newlines or start with whitespace.
+    pub fn to_indented<F: Fn(&V) -> String>(&self, indent: &str, label: F) -> String {
+        let mut text = String::new();
+        for (id, depth) in self.depths(0..self.len()) {
+            for _ in 0..depth {
+                text.push_str(indent);
+            }
+            text.push_str(&label(&self.nodes[id].value));
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Render the tree as a Markdown nested bullet list, indented by two
+    /// spaces per level. Labels are written as-is, so `label` should escape
+    /// any Markdown syntax if that matters.
+    pub fn to_markdown<F: Fn(&V) -> String>(&self, label: F) -> String {
+        self.to_indented("  ", |value| format!("- {}", label(value)))
+    }
+
+    /// Write the tree as JSON, where each node is an object like
+    /// `{"value":...,"children":[...]}` and `value_to_json` gives the JSON for
+    /// each value. If there is exactly one root then that is the top-level
+    /// value; otherwise the roots are written in an array. This streams the
+    /// output so it doesn't build the whole string in memory.
+    #[cfg(feature = "std")]
+    pub fn write_json<Wr: std::io::Write, F: Fn(&V) -> String>(
+        &self,
+        writer: &mut Wr,
+        value_to_json: F,
+    ) -> std::io::Result<()> {
+        let array = self.num_roots() != 1;
+        if array {
+            writer.write_all(b"[")?;
+        }
+        // An open straight after a close means it's a sibling of the node
+        // that just closed.
+        let mut after_close = false;
+        for event in self.events() {
+            match event {
+                Event::Open(_, node) => {
+                    if after_close {
+                        writer.write_all(b",")?;
+                    }
+                    write!(
+                        writer,
+                        "{{\"value\":{},\"children\":[",
+                        value_to_json(&node.value)
+                    )?;
+                    after_close = false;
+                }
+                Event::Close(_) => {
+                    writer.write_all(b"]}")?;
+                    after_close = true;
+                }
+            }
+        }
+        if array {
+            writer.write_all(b"]")?;
+        }
+        Ok(())
+    }
+
+    /// Count the nodes at each depth within a subtree, relative to its root.
+    /// Index 0 is the root itself so it is always 1. Returns an empty `Vec`
+    /// for invalid IDs.
+    pub fn node_counts_by_relative_depth(&self, id: K) -> Vec<usize> {
+        let mut counts = Vec::new();
+        for (_, depth) in self.depths(self.subtree_range(id.into())) {
+            if depth == counts.len() {
+                counts.push(0);
+            }
+            counts[depth] += 1;
+        }
+        counts
+    }
+
+    /// Compute an aggregate range over a node and all its descendants, for
+    /// example the source span of a subtree when `V` stores positions.
+    /// `get` extracts a `(start, end)` pair from each value and `merge`
+    /// combines two pairs. Returns `None` for invalid IDs.
+    pub fn subtree_bounds<B>(
+        &self,
+        id: K,
+        get: impl Fn(&V) -> (B, B),
+        merge: impl Fn((B, B), (B, B)) -> (B, B),
+    ) -> Option<(B, B)> {
+        self.nodes[self.subtree_range(id.into())]
+            .iter()
+            .map(|node| get(&node.value))
+            .reduce(merge)
+    }
+
+    /// Sort siblings into a canonical order so that trees which only differ
+    /// in the order of children (at any level) become identical. Subtrees are
+    /// compared by their root value first and then recursively by their
+    /// children. Roots are sorted too.
+    ///
+    /// This resets the current node as if you had `up()`d all the way to the
+    /// top, because the last node may have moved.
+    pub fn canonicalize(&mut self)
+    where
+        V: Ord,
+    {
+        // Canonical child order for each node. We work backwards so that
+        // children are always sorted before their parents are compared.
+        let mut children: Vec<Vec<usize>> = Vec::new();
+        children.resize_with(self.len(), Vec::new);
+        for id in (0..self.len()).rev() {
+            let mut sorted = self.child_ids(id);
+            sorted.sort_by(|&a, &b| self.cmp_canonical(&children, a, b));
+            children[id] = sorted;
+        }
+        let mut roots = self.root_ids();
+        roots.sort_by(|&a, &b| self.cmp_canonical(&children, a, b));
+        self.reorder_children(roots, &children);
+    }
+
+    /// Remove a node and all its descendants and return them as a new tree,
+    /// with `id` as its root. Later nodes are shifted down to fill the gap so
+    /// this is O(n). If the current node is removed then its closest
+    /// remaining ancestor becomes current.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` is not a valid ID.
+    pub fn remove_subtree(&mut self, id: K) -> Tree<K, V, I> {
+        let id = id.into();
+        assert!(id < self.len(), "invalid ID {id}");
+        Tree {
+            nodes: self.remove_block(id),
+            parent_stack: Vec::new(),
+            single_root: false,
+        }
+    }
+
+    /// Split the subtree rooted at `id` off into a new tree. Every node is the
+    /// start of a subtree, so this always splits on a subtree boundary: the
+    /// new tree contains `id` and its descendants, and unlike
+    /// `Vec::split_off()` any nodes after them in pre-order (e.g. later
+    /// siblings) stay in `self`. This is the same as `remove_subtree()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` is not a valid ID.
+    pub fn split_off(&mut self, id: K) -> Tree<K, V, I> {
+        self.remove_subtree(id)
+    }
+
+    /// Check whether the subtree rooted at `id` has the same shape and values
+    /// as the subtree rooted at `other_id` in `other`. Returns false if either
+    /// ID is invalid.
+    pub fn subtree_equals(&self, id: K, other: &Tree<K, V, I>, other_id: K) -> bool
+    where
+        V: PartialEq,
+    {
+        let a = &self.nodes[self.subtree_range(id.into())];
+        let b = &other.nodes[other.subtree_range(other_id.into())];
+        // The descendant counts in pre-order are enough to determine the shape.
+        !a.is_empty()
+            && a.len() == b.len()
+            && a.iter()
+                .zip(b)
+                .all(|(a, b)| a.value == b.value && a.num_descendants() == b.num_descendants())
+    }
+
+    /// Attach all the nodes of `subtree` as descendants of `parent`, after its
+    /// existing descendants. The roots of `subtree` become children of
+    /// `parent`. This is the inverse of `remove_subtree()`. Later nodes are
+    /// shifted along so this is O(n). If `parent` is an ancestor of the
+    /// current node then it becomes the current node.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `parent` is not a valid ID.
+    pub fn graft(&mut self, parent: K, subtree: Tree<K, V, I>) {
+        let parent = parent.into();
+        assert!(parent < self.len(), "invalid parent ID {parent}");
+        let pos = self.subtree_range(parent).end;
+        self.insert_block(Some(parent), pos, subtree.nodes);
+    }
+
+    /// Copy the subtree rooted at `id` into `dst` as the last child of
+    /// `parent`, like cloning it and then using `graft()` but without
+    /// building the intermediate tree. If `parent` is an ancestor of `dst`'s
+    /// current node then it becomes the current node.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` is not a valid ID in this tree or `parent` is not a
+    /// valid ID in `dst`.
+    pub fn clone_subtree_into(&self, id: K, dst: &mut Tree<K, V, I>, parent: K)
+    where
+        V: Clone,
+    {
+        let id = id.into();
+        let parent = parent.into();
+        assert!(id < self.len(), "invalid ID {id}");
+        assert!(parent < dst.len(), "invalid parent ID {parent}");
+        let block = self.nodes[self.subtree_range(id)]
+            .iter()
+            .enumerate()
+            .map(|(i, node)| {
+                let mut node = node.clone();
+                node.set_parent(if i == 0 { 0 } else { node.parent_index() - id });
+                node
+            })
+            .collect();
+        let pos = dst.subtree_range(parent).end;
+        dst.insert_block(Some(parent), pos, block);
+    }
+
+    /// Merge `other` into this tree. Roots, and then the children of merged
+    /// nodes, are matched by `key`: a node from `other` is merged into the
+    /// first node here with the same key by calling `combine`, and their
+    /// children are merged recursively. Nodes from `other` with no match are
+    /// added along with their descendants after the existing children (or
+    /// roots), in their original order.
+    ///
+    /// This resets the current node as if you had `up()`d all the way to the
+    /// top, because the last node may have moved.
+    pub fn merge_by_key<B, F, G>(&mut self, other: Tree<K, V, I>, key: F, combine: G)
+    where
+        B: PartialEq,
+        F: Fn(&V) -> B,
+        G: Fn(&mut V, V),
+    {
+        let single_root = self.single_root;
+        let mut a = mem::take(self).into_options();
+        let mut b = other.into_options();
+        self.reserve(a.len() + b.len());
+        let (a_roots, b_roots) = (a.root_ids(), b.root_ids());
+        self.merge_children(&mut a, &mut b, a_roots, b_roots, (&key, &combine));
+        self.recompute_descendant_counts();
+        self.parent_stack.clear();
+        self.single_root = single_root;
+    }
+
+    /// Insert a new node on the edge between `parent` and its child `child`,
+    /// so that the new node becomes a child of `parent` and `child` (with its
+    /// subtree) becomes the only child of the new node. Returns the ID of the
+    /// new node, which is the old ID of `child`. This shifts all later nodes
+    /// along so it is O(n).
+    ///
+    /// Returns an error if `child` is not an immediate child of `parent`.
+    pub fn insert_between(&mut self, parent: K, child: K, value: V) -> Result<K, NotAnEdge> {
+        let parent = parent.into();
+        let child = child.into();
+        match self.nodes.get(child) {
+            Some(node) if node.parent_index() == parent && parent != child => {}
+            _ => return Err(NotAnEdge),
+        }
+        let num_descendants = self.subtree_range(child).len();
+        self.insert_block(Some(parent), child, vec![Node::new(value, 0)]);
+        self.nodes[child].set_num_descendants(num_descendants);
+        self.nodes[child].set_num_children(1);
+        self.nodes[child + 1].set_parent(child);
+        // `child` was already one of the parent's children.
+        let parent = &mut self.nodes[parent];
+        parent.set_num_children(parent.num_children() - 1);
+        // If the current node is inside the subtree then the new node is one
+        // of its parents.
+        if let Some(i) = self.parent_stack.iter().position(|&id| id == child + 1) {
+            self.parent_stack.insert(i, child);
+        }
+        Ok(child.into())
+    }
+
+    /// Move a node and its descendants so that it becomes the last child of
+    /// `new_parent`. IDs of the moved nodes and of nodes between the old and
+    /// new positions change. If the current node is moved then its closest
+    /// remaining ancestor becomes current, and if `new_parent` is an ancestor
+    /// of the current node then `new_parent` becomes current. This is O(n).
+    ///
+    /// Returns an error if either ID is invalid or if `new_parent` is inside
+    /// the moved subtree.
+    pub fn move_subtree(&mut self, id: K, new_parent: K) -> Result<(), MoveError> {
+        let id = id.into();
+        let mut new_parent = new_parent.into();
+        if id >= self.len() || new_parent >= self.len() {
+            return Err(MoveError::InvalidId);
+        }
+        let range = self.subtree_range(id);
+        if range.contains(&new_parent) {
+            return Err(MoveError::WouldCycle);
+        }
+        let subtree = self.remove_subtree(id.into());
+        if new_parent >= range.end {
+            new_parent -= range.len();
+        }
+        self.graft(new_parent.into(), subtree);
+        Ok(())
+    }
+
+    /// Replace the values of all nodes, in pre-order. The tree is not
+    /// modified if `values` doesn't contain exactly `len()` values.
+    pub fn set_all_values(
+        &mut self,
+        values: impl IntoIterator<Item = V>,
+    ) -> Result<(), LengthMismatch> {
+        let values: Vec<V> = values.into_iter().collect();
+        if values.len() != self.len() {
+            return Err(LengthMismatch {
+                expected: self.len(),
+                actual: values.len(),
+            });
+        }
+        for (node, value) in self.nodes.iter_mut().zip(values) {
+            node.value = value;
+        }
+        Ok(())
+    }
+
+    /// Call `f` with the depth and value of each node, in pre-order.
+    pub fn visit_mut<F: FnMut(usize, &mut V)>(&mut self, mut f: F) {
+        // The last index of the subtree of each open ancestor.
+        let mut ends: Vec<usize> = Vec::new();
+        for (id, node) in self.nodes.iter_mut().enumerate() {
+            while ends.last().is_some_and(|&end| end < id) {
+                ends.pop();
+            }
+            f(ends.len(), &mut node.value);
+            ends.push(id + node.num_descendants());
+        }
+    }
+
+    /// Get the ID of the first node (in pre-order) whose value matches `pred`.
+    pub fn find<F: Fn(&V) -> bool>(&self, pred: F) -> Option<K> {
+        self.nodes
+            .iter()
+            .position(|node| pred(&node.value))
+            .map(|id| id.into())
+    }
+
+    /// Check whether any node has the value `value`.
+    pub fn contains_value(&self, value: &V) -> bool
+    where
+        V: PartialEq,
+    {
+        self.nodes.iter().any(|node| node.value == *value)
+    }
+
+    /// Get the ID of the first node (in pre-order) with the value `value`.
+    pub fn position_of_value(&self, value: &V) -> Option<K>
+    where
+        V: PartialEq,
+    {
+        self.find(|other| other == value)
+    }
+
+    /// Iterate over the IDs of all nodes whose values match `pred`, in
+    /// pre-order.
+    pub fn find_all<'a, F: Fn(&V) -> bool + 'a>(&'a self, pred: F) -> impl Iterator<Item = K> + 'a {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(move |(_, node)| pred(&node.value))
+            .map(|(id, _)| id.into())
+    }
+
+    /// Get the ID of the first node (in pre-order) in the subtree rooted at
+    /// `root` (including `root` itself) whose value matches `pred`.
+    pub fn find_in_subtree<F: Fn(&V) -> bool>(&self, root: K, pred: F) -> Option<K> {
+        let range = self.subtree_range(root.into());
+        let start = range.start;
+        self.nodes[range]
+            .iter()
+            .position(|node| pred(&node.value))
+            .map(|offset| (start + offset).into())
+    }
+
+    /// Compare two trees' nodes (values and structure), ignoring the current
+    /// node stack. Unlike `==` this considers trees equal even if they were
+    /// left at different current nodes after building.
+    pub fn eq_shape(&self, other: &Tree<K, V, I>) -> bool
+    where
+        V: PartialEq,
+    {
+        self.nodes == other.nodes
+    }
+
+    /// Compute a list of edits that turn this tree into `other`. Nodes are
+    /// matched by position: roots with roots, and then the children of
+    /// matched nodes with each other in order. Matched nodes with different
+    /// values are updated, unmatched nodes in `self` are removed along with
+    /// their descendants, and unmatched nodes in `other` are inserted.
+    ///
+    /// The edits are ordered so that they can be applied in sequence with
+    /// `apply_edits()`: all the updates, then the removals in descending ID
+    /// order, then the insertions in `other`'s pre-order. IDs in each edit
+    /// refer to the tree as it is when that edit is applied, so inserted
+    /// nodes end up with the same IDs as in `other`.
+    pub fn diff(&self, other: &Tree<K, V, I>) -> Vec<TreeEdit<V>>
+    where
+        V: PartialEq + Clone,
+    {
+        let mut edits = Vec::new();
+        self.diff_children(other, self.root_ids(), other.root_ids(), None, &mut edits);
+        // The sort is stable so insertions stay in pre-order.
+        edits.sort_by_key(|edit| match *edit {
+            TreeEdit::Update { .. } => (0, Reverse(0)),
+            TreeEdit::Remove { id } => (1, Reverse(id)),
+            TreeEdit::Insert { .. } => (2, Reverse(0)),
+        });
+        edits
+    }
+
+    /// Apply a list of edits, such as those produced by `diff()`, in order.
+    /// Returns an error if an edit refers to an invalid ID or an insertion
+    /// index is past the end of the parent's children. The edits before the
+    /// invalid one will already have been applied.
+    ///
+    /// Insertions after the current node's subtree move the current node up
+    /// to the new node's parent, or reset it if the new node is a root.
+    pub fn apply_edits(&mut self, edits: &[TreeEdit<V>]) -> Result<(), EditError>
+    where
+        V: Clone,
+    {
+        for edit in edits {
+            match *edit {
+                TreeEdit::Insert {
+                    parent,
+                    index,
+                    ref value,
+                } => {
+                    if parent.is_some_and(|parent| parent >= self.len()) {
+                        return Err(EditError::InvalidId);
+                    }
+                    let siblings = match parent {
+                        Some(parent) => self.child_ids(parent),
+                        None => self.root_ids(),
+                    };
+                    match (siblings.get(index), parent) {
+                        (Some(&pos), _) => {
+                            self.insert_block(parent, pos, vec![Node::new(value.clone(), 0)])
+                        }
+                        (None, Some(parent)) if index == siblings.len() => {
+                            self.push_child(parent.into(), value.clone());
+                        }
+                        (None, None) if index == siblings.len() => {
+                            self.insert_block(None, self.len(), vec![Node::new(value.clone(), 0)])
+                        }
+                        _ => return Err(EditError::InvalidIndex),
+                    }
+                }
+                TreeEdit::Remove { id } => {
+                    if id >= self.len() {
+                        return Err(EditError::InvalidId);
+                    }
+                    self.remove_subtree(id.into());
+                }
+                TreeEdit::Update { id, ref value } => {
+                    self.replace_value(id.into(), value.clone())
+                        .ok_or(EditError::InvalidId)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether two trees have the same structure, ignoring their values
+    /// (which can be of different types).
+    pub fn equal_topology<W>(&self, other: &Tree<K, W, I>) -> bool {
+        self.len() == other.len()
+            && self.nodes.iter().zip(&other.nodes).all(|(a, b)| {
+                a.parent_index() == b.parent_index() && a.num_descendants() == b.num_descendants()
+            })
+    }
+
+    /// Iterate through the values of two trees with the same structure
+    /// together, in pre-order. Returns `None` if the structures differ (see
+    /// `equal_topology()`).
+    pub fn zip_values<'a, W>(
+        &'a self,
+        other: &'a Tree<K, W, I>,
+    ) -> Option<impl Iterator<Item = (&'a V, &'a W)>> {
+        self.equal_topology(other).then(|| {
+            self.nodes
+                .iter()
+                .zip(&other.nodes)
+                .map(|(a, b)| (&a.value, &b.value))
+        })
+    }
+
+    /// Count the nodes whose values match `pred`.
+    pub fn count_where<F: Fn(&V) -> bool>(&self, pred: F) -> usize {
+        self.nodes.iter().filter(|node| pred(&node.value)).count()
+    }
+
+    /// Count the nodes that have no children.
+    pub fn count_leaves(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|node| node.num_descendants() == 0)
+            .count()
+    }
+
+    /// Count the nodes at a given depth, where roots are at level 0.
+    pub fn width_at_level(&self, level: usize) -> usize {
+        self.depths(0..self.len())
+            .filter(|&(_, depth)| depth == level)
+            .count()
+    }
+
+    /// Count the nodes at each depth. Index 0 is the number of roots.
+    pub fn level_widths(&self) -> Vec<usize> {
+        let mut widths = Vec::new();
+        for (_, depth) in self.depths(0..self.len()) {
+            if depth == widths.len() {
+                widths.push(0);
+            }
+            widths[depth] += 1;
+        }
+        widths
+    }
+
+    /// Get the IDs of all the nodes in breadth-first order, i.e. all the
+    /// roots, then all the nodes at depth 1 and so on. Nodes at the same
+    /// depth are in pre-order.
+    pub fn bfs_order(&self) -> Vec<K> {
+        let mut order = Vec::with_capacity(self.len());
+        let mut queue: VecDeque<usize> = self.root_ids().into();
+        while let Some(id) = queue.pop_front() {
+            order.push(id.into());
+            queue.extend(self.children(id.into()).map(|(child, _)| child.into()));
+        }
+        order
+    }
+
+    /// Get the position of each node in `bfs_order()`, indexed by ID.
+    pub fn preorder_to_bfs_rank(&self) -> Vec<usize> {
+        let mut ranks = vec![0; self.len()];
+        for (rank, id) in self.bfs_order().into_iter().enumerate() {
+            ranks[id.into()] = rank;
+        }
+        ranks
+    }
+
+    /// Check that the tree is consistent. This can be useful after modifying
+    /// nodes with `get_mut()` or building with unbalanced `push()` calls in
+    /// unusual ways. Returns an error describing the first problem found.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        for (id, node) in self.nodes.iter().enumerate() {
+            if node.parent_index() > id {
+                return Err(ValidationError::InvalidParent { id });
+            }
+        }
+        // Count the descendants using the parent indices. Children always come
+        // after their parents so we can do this in one backwards pass.
+        let mut counts = vec![0; self.len()];
+        for (id, node) in self.nodes.iter().enumerate().rev() {
+            if node.parent_index() != id {
+                counts[node.parent_index()] += counts[id] + 1;
+            }
+        }
+        for (id, (node, &count)) in self.nodes.iter().zip(&counts).enumerate() {
+            if node.num_descendants() != count {
+                return Err(ValidationError::WrongNumDescendants {
+                    id,
+                    expected: count,
+                    actual: node.num_descendants(),
+                });
+            }
+        }
+        // The counts are right but the descendants also need to be contiguous.
+        for (id, node) in self.nodes.iter().enumerate() {
+            if node.parent_index() != id && !self.subtree_range(node.parent_index()).contains(&id) {
+                return Err(ValidationError::OutsideParent { id });
+            }
+        }
+        let mut counts = vec![0; self.len()];
+        for (id, node) in self.nodes.iter().enumerate() {
+            if node.parent_index() != id {
+                counts[node.parent_index()] += 1;
+            }
+        }
+        for (id, (node, &count)) in self.nodes.iter().zip(&counts).enumerate() {
+            if node.num_children() != count {
+                return Err(ValidationError::WrongNumChildren {
+                    id,
+                    expected: count,
+                    actual: node.num_children(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Recalculate every node's number of descendants (and children) from
+    /// the parent indices. This will fix up the tree if the counts are wrong,
+    /// as long as the parents are valid and each subtree is contiguous.
+    pub fn recompute_descendant_counts(&mut self) {
+        for node in &mut self.nodes {
+            node.set_num_descendants(0);
+            node.set_num_children(0);
+        }
+        // Children always come after their parents so working backwards means
+        // each node's count is complete before it is added to its parent.
+        for id in (0..self.len()).rev() {
+            let node = &self.nodes[id];
+            let (parent, count) = (node.parent_index(), node.num_descendants());
+            if parent != id {
+                let parent = &mut self.nodes[parent];
+                parent.set_num_descendants(parent.num_descendants() + count + 1);
+                parent.set_num_children(parent.num_children() + 1);
+            }
+        }
+    }
+
+    /// Remove every node whose value doesn't match `keep`, along with all of
+    /// its descendants. Descendants of removed nodes are not passed to `keep`.
+    /// This works in place; see `filter_subtrees()` for a version that
+    /// returns a new tree.
+    pub fn retain_subtrees<F: FnMut(&V) -> bool>(&mut self, keep: F) {
+        let mask = self.subtree_mask(keep);
+        self.compact(&mask);
+    }
+
+    /// Remove children whose `key` is the same as the previous sibling's,
+    /// along with their descendants, for every node. Like `Vec::dedup_by_key()`
+    /// this only removes adjacent duplicates, so you may want to sort the
+    /// children first. Roots are not deduplicated. If the current node is
+    /// removed then its closest remaining ancestor becomes current.
+    pub fn dedup_children_by_key<B: PartialEq, F: FnMut(&V) -> B>(&mut self, mut key: F) {
+        let mut mask = vec![true; self.len()];
+        for id in 0..self.len() {
+            // Skip nodes that have already been removed.
+            if !mask[id] {
+                continue;
+            }
+            let mut previous = None;
+            for (child, node) in self.children(id.into()) {
+                let child_key = key(&node.value);
+                if previous.as_ref() == Some(&child_key) {
+                    mask[self.subtree_range(child.into())].fill(false);
+                } else {
+                    previous = Some(child_key);
+                }
+            }
+        }
+        self.compact(&mask);
+    }
+
+    /// Return a copy of the tree without the nodes whose values don't match
+    /// `keep`, or any of their descendants. Descendants of removed nodes are
+    /// not passed to `keep`. See `retain_subtrees()` for an in-place version.
+    pub fn filter_subtrees<F: FnMut(&V) -> bool>(&self, keep: F) -> Tree<K, V, I>
+    where
+        V: Clone,
+    {
+        let mask = self.subtree_mask(keep);
+        self.filtered(&mask)
+    }
+
+    /// Return a copy of the tree containing only the nodes whose values match
+    /// `keep`, along with all of their ancestors so that they stay connected
+    /// to their roots. This is useful for search results that show the path
+    /// to each match.
+    pub fn filter_keep_ancestors<F: Fn(&V) -> bool>(&self, keep: F) -> Tree<K, V, I>
+    where
+        V: Clone,
+    {
+        let mut mask = vec![false; self.len()];
+        // Parents come before their children so in reverse order we have
+        // already seen all the matches in a node's subtree by the time we
+        // reach it.
+        for (id, node) in self.nodes.iter().enumerate().rev() {
+            mask[id] |= keep(&node.value);
+            if mask[id] && node.parent_index() != id {
+                mask[node.parent_index()] = true;
+            }
+        }
+        self.filtered(&mask)
+    }
+
+    /// Remove every subtree whose root value matches `pred` and return them
+    /// as separate trees, in pre-order. Descendants of removed nodes are not
+    /// passed to `pred`. If the current node is removed then its closest
+    /// remaining ancestor becomes current. This is O(n).
+    pub fn extract_subtrees<F: FnMut(&V) -> bool>(&mut self, mut pred: F) -> Vec<Tree<K, V, I>> {
+        let mask = self.subtree_mask(|value| !pred(value));
+        let mut kept = Vec::with_capacity(mask.iter().filter(|&&keep| keep).count());
+        let mut trees: Vec<Tree<K, V, I>> = Vec::new();
+        // The ID of the root of the subtree currently being extracted.
+        let mut start = 0;
+        for (id, mut node) in mem::take(&mut self.nodes).into_iter().enumerate() {
+            if mask[id] {
+                kept.push(node);
+                continue;
+            }
+            if node.parent_index() == id || mask[node.parent_index()] {
+                start = id;
+                trees.push(Tree::new());
+            }
+            // Make the parent relative to the new tree, whose root is its own
+            // parent.
+            let parent = if id == start {
+                0
+            } else {
+                node.parent_index() - start
+            };
+            node.set_parent(parent);
+            trees.last_mut().unwrap().nodes.push(node);
+        }
+        self.nodes = kept;
+        self.remap_compacted(&mask);
+        trees
+    }
+
+    /// Remove every node that is more than `max_depth` levels below its root,
+    /// so roots are at depth 0. Nodes at `max_depth` become leaves.
+    pub fn prune_to_depth(&mut self, max_depth: usize) {
+        let mask: Vec<bool> = self
+            .depths(0..self.len())
+            .map(|(_, depth)| depth <= max_depth)
+            .collect();
+        self.compact(&mask);
+    }
+
+    /// Sort the children of every node by their values. Each child's subtree
+    /// moves with it. The sort is stable and roots are not reordered.
+    ///
+    /// This resets the current node as if you had `up()`d all the way to the
+    /// top, because the last node may have moved.
+    pub fn sort_children_by<F: FnMut(&V, &V) -> Ordering>(&mut self, mut cmp: F) {
+        let children: Vec<Vec<usize>> = (0..self.len())
+            .map(|id| {
+                let mut children = self.child_ids(id);
+                children.sort_by(|&a, &b| cmp(&self.nodes[a].value, &self.nodes[b].value));
+                children
+            })
+            .collect();
+        self.reorder_children(self.root_ids(), &children);
+    }
+
+    /// Reverse the order of a node's immediate children. Each child's subtree
+    /// moves with it but is otherwise unchanged.
+    ///
+    /// This resets the current node as if you had `up()`d all the way to the
+    /// top, because the last node may have moved.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` is not a valid ID.
+    pub fn reverse_children(&mut self, id: K) {
+        let id = id.into();
+        assert!(id < self.len(), "invalid ID {id}");
+        let end = self.subtree_range(id).end;
+        let mut order: Vec<usize> = (0..=id).collect();
+        for (child, _) in self.children(id.into()).rev() {
+            order.extend(self.subtree_range(child.into()));
+        }
+        order.extend(end..self.len());
+        self.reorder(&order);
+    }
+
+    /// Convert the subtree rooted at `id` into a recursive `Nested` tree.
+    /// Returns `None` for invalid IDs.
+    pub fn to_nested(&self, id: K) -> Option<Nested<V>>
+    where
+        V: Clone,
+    {
+        let id = id.into();
+        (id < self.len()).then(|| self.nested(id))
+    }
+
+    /// Convert the whole tree into a `Nested` tree for each root.
+    pub fn to_nested_forest(&self) -> Vec<Nested<V>>
+    where
+        V: Clone,
+    {
+        self.roots().map(|(id, _)| self.nested(id.into())).collect()
+    }
+
+    /// Insert a block of nodes at `pos`, with the block's roots becoming
+    /// children of `parent`, or roots if it is `None`. The block must be in
+    /// pre-order with its parent indices relative to the start of the block
+    /// (so its roots are their own parents). `pos` must be on a child boundary
+    /// of `parent`, i.e. just after the parent or one of its child subtrees.
+    ///
+    /// If the block goes after the current node's subtree, rather than at the
+    /// end of it, then the current node is no longer the last node on its
+    /// path, so `push()` could no longer add to it. In that case `parent`
+    /// becomes the current node (or there is no current node if it is `None`).
+    fn insert_block(&mut self, parent: Option<usize>, pos: usize, mut block: Vec<Node<K, V, I>>) {
+        let count = block.len();
+        if let Some(&current) = self.parent_stack.last() {
+            let subtree = self.subtree_range(current);
+            if pos > subtree.end
+                || (pos == subtree.end && !parent.is_some_and(|parent| subtree.contains(&parent)))
+            {
+                let keep = parent
+                    .and_then(|parent| self.parent_stack.iter().position(|&id| id == parent))
+                    .map_or(0, |i| i + 1);
+                self.parent_stack.truncate(keep);
+            }
+        }
+        // Existing nodes that point at or after the insertion point move back.
+        for node in &mut self.nodes[pos..] {
+            if node.parent_index() >= pos {
+                node.set_parent(node.parent_index() + count);
+            }
+        }
+        for entry in &mut self.parent_stack {
+            if *entry >= pos {
+                *entry += count;
+            }
+        }
+        let mut num_roots = 0;
+        for (i, node) in block.iter_mut().enumerate() {
+            num_roots += usize::from(node.parent_index() == i);
+            let node_parent = match parent {
+                Some(parent) if node.parent_index() == i => parent,
+                _ => node.parent_index() + pos,
+            };
+            node.set_parent(node_parent);
+        }
+        if let Some(parent) = parent {
+            self.for_each_ancestor(parent, |node| {
+                node.set_num_descendants(node.num_descendants() + count)
+            });
+            let parent = &mut self.nodes[parent];
+            parent.set_num_children(parent.num_children() + num_roots);
+        }
+        self.nodes.splice(pos..pos, block);
+    }
+
+    /// Iterate over the indices in `range` along with their depth relative to
+    /// the start of the range. `range` must cover whole subtrees, e.g. it can
+    /// be a `subtree_range()` or `0..len`.
+    fn depths(&self, range: Range<usize>) -> impl Iterator<Item = (usize, usize)> + '_ {
+        // The last index of the subtree of each open ancestor.
+        let mut ends: Vec<usize> = Vec::new();
+        range.map(move |id| {
+            while ends.last().is_some_and(|&end| end < id) {
+                ends.pop();
+            }
+            let depth = ends.len();
+            ends.push(id + self.nodes[id].num_descendants());
+            (id, depth)
+        })
+    }
+
+    /// Call `f` on a node and each of its parents.
+    fn for_each_ancestor(&mut self, mut id: usize, mut f: impl FnMut(&mut Node<K, V, I>)) {
+        loop {
+            let node = &mut self.nodes[id];
+            f(node);
+            if node.parent_index() == id {
+                break;
+            }
+            id = node.parent_index();
+        }
+    }
+
+    /// The range of indices covered by a node and its descendants. This is
+    /// empty for invalid IDs.
+    fn subtree_range(&self, id: usize) -> Range<usize> {
+        match self.nodes.get(id) {
+            Some(node) => id..id + 1 + node.num_descendants(),
+            None => 0..0,
+        }
+    }
+
+    /// Get the IDs of the immediate children of a node.
+    fn child_ids(&self, id: usize) -> Vec<usize> {
+        self.children(id.into())
+            .map(|(child, _)| child.into())
+            .collect()
+    }
+
+    /// Get the IDs of all the roots.
+    fn root_ids(&self) -> Vec<usize> {
+        self.roots().map(|(id, _)| id.into()).collect()
+    }
+
+    /// Compare two subtrees by value and then recursively by their children
+    /// in the order given by `children`. This uses an explicit stack rather
+    /// than recursion so that very deep trees can't overflow the call stack.
+    fn cmp_canonical(&self, children: &[Vec<usize>], a: usize, b: usize) -> Ordering
+    where
+        V: Ord,
+    {
+        let ordering = self.nodes[a].value.cmp(&self.nodes[b].value);
+        if ordering.is_ne() {
+            return ordering;
+        }
+        // Each frame is a pair of nodes with equal values and the number of
+        // their children that have been compared so far.
+        let mut stack = vec![(a, b, 0)];
+        while let Some((a, b, done)) = stack.last_mut() {
+            let (a, b) = (*a, *b);
+            match (children[a].get(*done), children[b].get(*done)) {
+                (Some(&a), Some(&b)) => {
+                    *done += 1;
+                    let ordering = self.nodes[a].value.cmp(&self.nodes[b].value);
+                    if ordering.is_ne() {
+                        return ordering;
+                    }
+                    stack.push((a, b, 0));
+                }
+                _ => {
+                    let ordering = children[a].len().cmp(&children[b].len());
+                    if ordering.is_ne() {
+                        return ordering;
+                    }
+                    stack.pop();
+                }
+            }
+        }
+        Ordering::Equal
+    }
+
+    /// Rearrange the nodes so that the roots and the children of each node
+    /// are in the given order. Each node's whole subtree moves with it. This
+    /// clears the current node stack.
+    fn reorder_children(&mut self, roots: Vec<usize>, children: &[Vec<usize>]) {
+        let mut order = Vec::with_capacity(self.len());
+        let mut stack = roots;
+        stack.reverse();
+        while let Some(id) = stack.pop() {
+            order.push(id);
+            stack.extend(children[id].iter().rev());
+        }
+        self.reorder(&order);
+    }
+
+    /// Rearrange the nodes so that `order[new_id] == old_id`. The new order
+    /// must be a valid pre-order for the existing parent relationships. This
+    /// clears the current node stack.
+    fn reorder(&mut self, order: &[usize]) {
+        let mut new_ids = vec![0; order.len()];
+        for (new_id, &old_id) in order.iter().enumerate() {
+            new_ids[old_id] = new_id;
+        }
+        let mut old_nodes: Vec<Option<Node<K, V, I>>> =
+            mem::take(&mut self.nodes).into_iter().map(Some).collect();
+        self.nodes = order
+            .iter()
+            .map(|&old_id| {
+                let mut node = old_nodes[old_id].take().expect("duplicate ID in order");
+                node.set_parent(new_ids[node.parent_index()]);
+                node
+            })
+            .collect();
+        self.parent_stack.clear();
+    }
+
+    /// Remove the subtree rooted at `id` and return its nodes, with the parent
+    /// indices made relative to the start of the block (the inverse of
+    /// `insert_block()`).
+    fn remove_block(&mut self, id: usize) -> Vec<Node<K, V, I>> {
+        let range = self.subtree_range(id);
+        let count = range.len();
+        let parent = self.nodes[id].parent_index();
+        if parent != id {
+            self.for_each_ancestor(parent, |node| {
+                node.set_num_descendants(node.num_descendants() - count)
+            });
+            let parent = &mut self.nodes[parent];
+            parent.set_num_children(parent.num_children() - 1);
+        }
+        let mut block: Vec<Node<K, V, I>> = self.nodes.drain(range.clone()).collect();
+        for (i, node) in block.iter_mut().enumerate() {
+            node.set_parent(if i == 0 { 0 } else { node.parent_index() - id });
+        }
+        for node in &mut self.nodes[id..] {
+            if node.parent_index() >= range.end {
+                node.set_parent(node.parent_index() - count);
+            }
+        }
+        self.parent_stack.retain(|entry| !range.contains(entry));
+        for entry in &mut self.parent_stack {
+            if *entry >= range.end {
+                *entry -= count;
+            }
+        }
+        block
+    }
+
+    /// Get a mask of the nodes that match `keep` and whose ancestors all
+    /// match `keep` too.
+    fn subtree_mask<F: FnMut(&V) -> bool>(&self, mut keep: F) -> Vec<bool> {
+        let mut mask = vec![true; self.len()];
+        let mut id = 0;
+        while id < self.len() {
+            if keep(&self.nodes[id].value) {
+                id += 1;
+            } else {
+                let range = self.subtree_range(id);
+                id = range.end;
+                mask[range].fill(false);
+            }
+        }
+        mask
+    }
+
+    /// Get the new ID of each node after removing the nodes that aren't in
+    /// `mask`.
+    fn compacted_ids(mask: &[bool]) -> Vec<usize> {
+        let mut next_id = 0;
+        mask.iter()
+            .map(|&keep| {
+                let id = next_id;
+                next_id += usize::from(keep);
+                id
+            })
+            .collect()
+    }
+
+    /// Remove all the nodes that aren't in `mask`. If a node is in the mask
+    /// then its parent must be too. The current node becomes the closest
+    /// remaining ancestor.
+    fn compact(&mut self, mask: &[bool]) {
+        let mut keep = mask.iter();
+        self.nodes.retain(|_| *keep.next().unwrap());
+        self.remap_compacted(mask);
+    }
+
+    /// Update the parents, current node stack and descendant counts after
+    /// the nodes that aren't in `mask` have been removed from `nodes`.
+    fn remap_compacted(&mut self, mask: &[bool]) {
+        let new_ids = Self::compacted_ids(mask);
+        for node in &mut self.nodes {
+            node.set_parent(new_ids[node.parent_index()]);
+        }
+        let kept = self.parent_stack.iter().take_while(|&&id| mask[id]).count();
+        self.parent_stack.truncate(kept);
+        for entry in &mut self.parent_stack {
+            *entry = new_ids[*entry];
+        }
+        self.recompute_descendant_counts();
+    }
+
+    /// Return a copy of the tree containing only the nodes in `mask`. If a
+    /// node is in the mask then its parent must be too.
+    fn filtered(&self, mask: &[bool]) -> Tree<K, V, I>
+    where
+        V: Clone,
+    {
+        let new_ids = Self::compacted_ids(mask);
+        let nodes = self
+            .nodes
+            .iter()
+            .zip(mask)
+            .filter(|(_, &keep)| keep)
+            .map(|(node, _)| Node::new(node.value.clone(), new_ids[node.parent_index()]))
+            .collect();
+        let mut tree = Tree {
+            nodes,
+            parent_stack: Vec::new(),
+            single_root: self.single_root,
+        };
+        tree.recompute_descendant_counts();
+        tree
+    }
+
+    /// Convert the subtree rooted at `id`, which must be valid, into a
+    /// recursive `Nested` tree. This builds it from the bottom up rather than
+    /// recursing so that very deep trees can't overflow the call stack.
+    fn nested(&self, id: usize) -> Nested<V>
+    where
+        V: Clone,
+    {
+        // Working backwards, each node's children are finished before it and
+        // are at the top of the stack, with the first child last.
+        let mut finished: Vec<Nested<V>> = Vec::new();
+        for node in self.nodes[self.subtree_range(id)].iter().rev() {
+            let mut children = finished.split_off(finished.len() - node.num_children());
+            children.reverse();
+            finished.push(Nested {
+                value: node.value.clone(),
+                children,
+            });
+        }
+        finished.pop().unwrap()
+    }
+
+    /// Append a `Nested` tree as a new root. This uses an explicit stack
+    /// rather than recursion so that very deep trees can't overflow the call
+    /// stack. The descendant counts are not updated.
+    fn push_nested(&mut self, root: Nested<V>) {
+        let start = self.len();
+        self.nodes.push(Node::new(root.value, start));
+        // Each frame is a node's ID and an iterator over its remaining
+        // children. Moving the children out one at a time also means the
+        // `Nested` tree isn't dropped recursively.
+        let mut stack = vec![(start, root.children.into_iter())];
+        while let Some((parent, children)) = stack.last_mut() {
+            match children.next() {
+                Some(child) => {
+                    let id = self.len();
+                    self.nodes.push(Node::new(child.value, *parent));
+                    stack.push((id, child.children.into_iter()));
+                }
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+    }
+
+    /// Diff the nodes `a` in `self` against the nodes `b` in `other`, matching
+    /// them by position. `b` are the children of `parent` in `other`, or roots
+    /// if it is `None`. This uses an explicit stack rather than recursion so
+    /// that very deep trees can't overflow the call stack.
+    fn diff_children(
+        &self,
+        other: &Tree<K, V, I>,
+        a: Vec<usize>,
+        b: Vec<usize>,
+        parent: Option<usize>,
+        edits: &mut Vec<TreeEdit<V>>,
+    ) where
+        V: PartialEq + Clone,
+    {
+        // Each frame is the siblings from each tree, their parent in `other`
+        // and the number of pairs that have been diffed so far.
+        let mut stack = vec![(a, b, parent, 0)];
+        while let Some((a, b, parent, done)) = stack.last_mut() {
+            if let (Some(&a), Some(&b)) = (a.get(*done), b.get(*done)) {
+                *done += 1;
+                if self.nodes[a].value != other.nodes[b].value {
+                    edits.push(TreeEdit::Update {
+                        id: a,
+                        value: other.nodes[b].value.clone(),
+                    });
+                }
+                stack.push((self.child_ids(a), other.child_ids(b), Some(b), 0));
+            } else {
+                edits.extend(a.iter().skip(b.len()).map(|&id| TreeEdit::Remove { id }));
+                for (index, &b) in b.iter().enumerate().skip(a.len()) {
+                    other.diff_insert(b, *parent, index, edits);
+                }
+                stack.pop();
+            }
+        }
+    }
+
+    /// Add edits that insert a copy of the subtree rooted at `id`, as the
+    /// `index`th child of `parent`.
+    fn diff_insert(
+        &self,
+        id: usize,
+        parent: Option<usize>,
+        index: usize,
+        edits: &mut Vec<TreeEdit<V>>,
+    ) where
+        V: Clone,
+    {
+        // Children are pushed in reverse so that they are popped in pre-order.
+        let mut stack = vec![(id, parent, index)];
+        while let Some((id, parent, index)) = stack.pop() {
+            edits.push(TreeEdit::Insert {
+                parent,
+                index,
+                value: self.nodes[id].value.clone(),
+            });
+            let children = self.child_ids(id).into_iter().enumerate().rev();
+            stack.extend(children.map(|(index, child)| (child, Some(id), index)));
+        }
+    }
+
+    /// Convert the values to `Option`s so that they can be moved out one at a
+    /// time.
+    fn into_options(self) -> Tree<K, Option<V>, I> {
+        Tree {
+            nodes: self
+                .nodes
+                .into_iter()
+                .map(|node| Node {
+                    value: Some(node.value),
+                    parent: node.parent,
+                    num_descendants: node.num_descendants,
+                    num_children: node.num_children,
+                    _key_type: PhantomData,
+                })
+                .collect(),
+            parent_stack: self.parent_stack,
+            single_root: self.single_root,
+        }
+    }
+
+    /// Append the merge of the nodes `a_ids` from the first tree and `b_ids`
+    /// from the second as roots, in pre-order.
+    /// The merged values are moved out of the trees. This uses an explicit
+    /// stack rather than recursion so that very deep trees can't overflow the
+    /// call stack. The descendant counts are not updated.
+    fn merge_children<B, F, G>(
+        &mut self,
+        a: &mut Tree<K, Option<V>, I>,
+        b: &mut Tree<K, Option<V>, I>,
+        a_ids: Vec<usize>,
+        b_ids: Vec<usize>,
+        (key, combine): (&F, &G),
+    ) where
+        B: PartialEq,
+        F: Fn(&V) -> B,
+        G: Fn(&mut V, V),
+    {
+        // Each frame is the parent of the merged nodes in this tree, the nodes
+        // from `a`, the nodes from `b` that match each of them, the unmatched
+        // nodes from `b`, and the number of nodes from `a` merged so far.
+        let (matches, unmatched) = Self::match_by_key(a, b, &a_ids, &b_ids, key);
+        let mut stack = vec![(None, a_ids, matches, unmatched, 0)];
+        while let Some((parent, a_ids, matches, unmatched, done)) = stack.last_mut() {
+            let parent = *parent;
+            if let Some(&a_id) = a_ids.get(*done) {
+                let mut value = a.nodes[a_id].value.take().unwrap();
+                let mut b_children = Vec::new();
+                for &b_id in &matches[*done] {
+                    combine(&mut value, b.nodes[b_id].value.take().unwrap());
+                    b_children.extend(b.child_ids(b_id));
+                }
+                *done += 1;
+                let id = self.len();
+                self.nodes.push(Node::new(value, parent.unwrap_or(id)));
+                let a_children = a.child_ids(a_id);
+                let (matches, unmatched) = Self::match_by_key(a, b, &a_children, &b_children, key);
+                stack.push((Some(id), a_children, matches, unmatched, 0));
+            } else {
+                for &id in unmatched.iter() {
+                    self.push_taken(b, id, parent);
+                }
+                stack.pop();
+            }
+        }
+    }
+
+    /// Find the nodes in `b_ids` whose key matches each node in `a_ids`, and
+    /// the ones that don't match any of them.
+    fn match_by_key<B, F>(
+        a: &Tree<K, Option<V>, I>,
+        b: &Tree<K, Option<V>, I>,
+        a_ids: &[usize],
+        b_ids: &[usize],
+        key: &F,
+    ) -> (Vec<Vec<usize>>, Vec<usize>)
+    where
+        B: PartialEq,
+        F: Fn(&V) -> B,
+    {
+        let a_keys: Vec<B> = a_ids
+            .iter()
+            .map(|&id| key(a.nodes[id].value.as_ref().unwrap()))
+            .collect();
+        let mut matches = vec![Vec::new(); a_ids.len()];
+        let mut unmatched = Vec::new();
+        for &id in b_ids {
+            let b_key = key(b.nodes[id].value.as_ref().unwrap());
+            match a_keys.iter().position(|a_key| *a_key == b_key) {
+                Some(i) => matches[i].push(id),
+                None => unmatched.push(id),
+            }
+        }
+        (matches, unmatched)
+    }
+
+    /// Append the subtree rooted at `id` in `tree` as a child of `parent` (or
+    /// as a root), moving the values out of `tree`. The descendant counts are
+    /// not updated.
+    fn push_taken(&mut self, tree: &mut Tree<K, Option<V>, I>, id: usize, parent: Option<usize>) {
+        let start = self.len();
+        for i in tree.subtree_range(id) {
+            let node = &mut tree.nodes[i];
+            let node_parent = match parent {
+                _ if i != id => node.parent_index() - id + start,
+                Some(parent) => parent,
+                None => start,
+            };
+            self.nodes
+                .push(Node::new(node.value.take().unwrap(), node_parent));
+        }
+    }
+}
+
+impl<K, I: IndexType> Tree<K, String, I>
+where
+    usize: Into<K>,
+    K: Into<usize>,
+{
+    /// Parse an indented outline, where each line is a node and its depth is
+    /// the number of times it starts with `indent` (e.g. two spaces). Blank
+    /// lines are ignored.
+    ///
+    /// Returns an error if a line is indented more than one level deeper
+    /// than the previous node, or if its indentation is not a whole number
+    /// of `indent`s.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `indent` is empty.
+    pub fn from_indented(text: &str, indent: &str) -> Result<Self, IndentError> {
+        assert!(!indent.is_empty(), "indent must not be empty");
+        let mut tree = Self::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut level = 0;
+            let mut content = line;
+            while let Some(rest) = content.strip_prefix(indent) {
+                level += 1;
+                content = rest;
+            }
+            if content.starts_with(char::is_whitespace) {
+                return Err(IndentError::Misaligned { line: i + 1 });
+            }
+            if level > tree.parent_stack.len() {
+                return Err(IndentError::OverIndented { line: i + 1 });
+            }
+            tree.parent_stack.truncate(level);
+            tree.push(String::from(content));
+        }
+        Ok(tree)
+    }
+}
+
+impl<K, V: Debug, I: IndexType> Debug for Tree<K, V, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tree")
+            .field("nodes", &self.nodes)
+            .field("parent_stack", &self.parent_stack)
+            .field("single_root", &self.single_root)
+            .finish()
+    }
+}
+
+/// Render the tree as ASCII art, one node per line, e.g.
+///
+/// ```text
+/// 0
+/// ├── 1
+/// │   └── 2
+/// └── 3
+/// ```
+///
+/// Multiple roots are rendered one after another.
+impl<K, V: Display, I: IndexType> Display for Tree<K, V, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The open ancestors of the current node, as the last index in their
+        // subtree and whether they are the last child of their parent.
+        let mut stack: Vec<(usize, bool)> = Vec::new();
+        for (id, node) in self.nodes.iter().enumerate() {
+            while stack.last().is_some_and(|&(end, _)| end < id) {
+                stack.pop();
+            }
+            let end = id + node.num_descendants();
+            let is_last = stack
+                .last()
+                .is_none_or(|&(parent_end, _)| end == parent_end);
+            if let Some((_root, ancestors)) = stack.split_first() {
+                for &(_, ancestor_is_last) in ancestors {
+                    f.write_str(if ancestor_is_last { "    " } else { "│   " })?;
+                }
+                f.write_str(if is_last { "└── " } else { "├── " })?;
+            }
+            writeln!(f, "{}", node.value)?;
+            stack.push((end, is_last));
+        }
+        Ok(())
+    }
+}
+
+impl<K, V: PartialEq, I: IndexType> PartialEq for Tree<K, V, I> {
+    fn eq(&self, other: &Self) -> bool {
+        // `single_root` only affects how the tree is built, so it isn't
+        // compared.
+        self.nodes == other.nodes && self.parent_stack == other.parent_stack
+    }
+}
+
+impl<K, V: Hash, I: IndexType> Hash for Tree<K, V, I> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.nodes.hash(state);
+        self.parent_stack.hash(state);
+    }
+}
+
+impl<K, V: Clone, I: IndexType> Clone for Tree<K, V, I> {
+    fn clone(&self) -> Self {
+        Self {
+            nodes: self.nodes.clone(),
+            parent_stack: self.parent_stack.clone(),
+            single_root: self.single_root,
+        }
+    }
+}
+
+impl<K, V: Eq, I: IndexType> Eq for Tree<K, V, I> {}
+
+/// Graft each tree under the current node, in order. If there is no current
+/// node then they are added as new roots.
+impl<K, V, I: IndexType> Extend<Tree<K, V, I>> for Tree<K, V, I>
+where
+    usize: Into<K>,
+    K: Into<usize>,
+{
+    fn extend<T: IntoIterator<Item = Tree<K, V, I>>>(&mut self, iter: T) {
+        for subtree in iter {
+            match self.current() {
+                Some(parent) => self.graft(parent, subtree),
+                None if self.single_root && !self.is_empty() => self.graft(0.into(), subtree),
+                None => self.insert_block(None, self.len(), subtree.nodes),
+            }
+        }
+    }
+}
+
+impl<K, V, I: IndexType> AsRef<[Node<K, V, I>]> for Tree<K, V, I> {
+    fn as_ref(&self) -> &[Node<K, V, I>] {
+        &self.nodes
+    }
+}
+
+/// A builder for a `Tree`, which only allows adding nodes in pre-order. Call
+/// `build()` to get the finished tree.
+pub struct TreeBuilder<K, V, I = usize> {
+    tree: Tree<K, V, I>,
+}
+
+impl<K, V, I: IndexType> Default for TreeBuilder<K, V, I> {
+    fn default() -> Self {
+        Self {
+            tree: Default::default(),
+        }
+    }
+}
+
+impl<K, V, I: IndexType> TreeBuilder<K, V, I>
+where
+    usize: Into<K>,
+    K: Into<usize>,
+{
+    /// Create a builder for an empty tree.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Push a child of the current node, which becomes the current node. See
+    /// `Tree::push()`.
+    pub fn push(&mut self, value: V) -> K {
+        self.tree.push(value)
+    }
+
+    /// Set the current node to its parent. See `Tree::up()`.
+    pub fn up(&mut self) -> Option<K> {
+        self.tree.up()
+    }
+
+    /// Push a node, call `build_children` to add its children, and then go
+    /// back up to the node that was current before. See `Tree::child()`.
+    pub fn child<F: FnOnce(&mut TreeBuilder<K, V, I>)>(
+        &mut self,
+        value: V,
+        build_children: F,
+    ) -> K {
+        self.tree.child(value, |tree| {
+            let mut builder = TreeBuilder {
+                tree: mem::take(tree),
+            };
+            build_children(&mut builder);
+            *tree = builder.tree;
+        })
+    }
+
+    /// Finish building and return the tree, without a current node.
+    pub fn build(mut self) -> Tree<K, V, I> {
+        self.tree.finalize();
+        self.tree
+    }
+}
+
+/// A conventional recursive tree, where each node owns its children. This is
+/// useful for interoperating with code that doesn't use flat trees. Note that
+/// dropping, cloning or comparing a `Nested` tree recurses once per level, so
+/// very deep ones can overflow the stack; `Tree::from_nested()` doesn't.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Nested<V> {
+    pub value: V,
+    pub children: Vec<Nested<V>>,
+}
+
+/// An edit produced by `Tree::diff()`. IDs are plain indices into the tree as
+/// it is when the edit is applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeEdit<V> {
+    /// Insert a new leaf node as the `index`th child of `parent`, or as the
+    /// `index`th root if it is `None`.
+    Insert {
+        parent: Option<usize>,
+        index: usize,
+        value: V,
+    },
+    /// Remove a node and all its descendants.
+    Remove { id: usize },
+    /// Replace the value of a node.
+    Update { id: usize, value: V },
+}
+
+/// An event produced by `Tree::events()`.
+pub enum Event<'a, K, V, I = usize> {
+    /// The start of a node, before its descendants.
+    Open(K, &'a Node<K, V, I>),
+    /// The end of a node, after its descendants.
+    Close(K),
+}
+
+impl<K: Debug, V: Debug, I: IndexType> Debug for Event<'_, K, V, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Open(id, node) => f.debug_tuple("Open").field(id).field(node).finish(),
+            Self::Close(id) => f.debug_tuple("Close").field(id).finish(),
+        }
+    }
+}
+
+impl<K: PartialEq, V: PartialEq, I: IndexType> PartialEq for Event<'_, K, V, I> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Open(a, a_node), Self::Open(b, b_node)) => a == b && a_node == b_node,
+            (Self::Close(a), Self::Close(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl<K: Eq, V: Eq, I: IndexType> Eq for Event<'_, K, V, I> {}
+
+impl<K: Clone, V, I: IndexType> Clone for Event<'_, K, V, I> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Open(id, node) => Self::Open(id.clone(), node),
+            Self::Close(id) => Self::Close(id.clone()),
+        }
+    }
+}
+
+impl<K: Copy, V, I: IndexType> Copy for Event<'_, K, V, I> {}
+
+/// Error returned by `Tree::try_up()` when the current node has no parent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AtTopError;
+
+impl Display for AtTopError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("already at the top of the tree")
+    }
+}
+
+impl Error for AtTopError {}
+
+/// Error returned by `Tree::insert_between()` when the nodes are not a parent
+/// and its immediate child.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotAnEdge;
+
+impl Display for NotAnEdge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("nodes are not a parent and child")
+    }
+}
+
+impl Error for NotAnEdge {}
+
+/// Error returned by `Tree::move_subtree()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveError {
+    /// One of the IDs is not in the tree.
+    InvalidId,
+    /// The new parent is inside the subtree being moved.
+    WouldCycle,
+}
+
+impl Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidId => f.write_str("invalid node ID"),
+            Self::WouldCycle => f.write_str("cannot move a node inside itself"),
+        }
+    }
+}
+
+impl Error for MoveError {}
+
+/// Error returned by `Tree::apply_edits()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditError {
+    /// An edit refers to a node that is not in the tree.
+    InvalidId,
+    /// An insertion index is more than the number of siblings.
+    InvalidIndex,
+}
+
+impl Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidId => f.write_str("invalid node ID"),
+            Self::InvalidIndex => f.write_str("insertion index out of range"),
+        }
+    }
+}
+
+impl Error for EditError {}
+
+/// Error returned by `Tree::push_checked()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushError {
+    /// The current node stack refers to a node that is not in the tree.
+    InvalidCurrent,
+    /// The tree already has as many nodes as its index type can store.
+    TooManyNodes,
+}
+
+impl Display for PushError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidCurrent => f.write_str("current node is not in the tree"),
+            Self::TooManyNodes => f.write_str("too many nodes for the tree's index type"),
+        }
+    }
+}
+
+impl Error for PushError {}
+
+/// Error returned by `Tree::from_edges()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildError {
+    /// An edge or root refers to a node that doesn't have a value.
+    InvalidId { id: usize },
+    /// The node is the child of more than one edge, or is a root and a child.
+    MultipleParents { id: usize },
+    /// The node is part of (or below) a cycle so it can't be reached from
+    /// the roots.
+    Cycle { id: usize },
+    /// The node has no parent and is not a root.
+    Disconnected { id: usize },
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidId { id } => write!(f, "invalid node ID {id}"),
+            Self::MultipleParents { id } => write!(f, "node {id} has more than one parent"),
+            Self::Cycle { id } => write!(f, "node {id} is in a cycle"),
+            Self::Disconnected { id } => write!(f, "node {id} is not connected to a root"),
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+/// Error returned by `Tree::set_all_values()` when the number of values
+/// doesn't match the number of nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LengthMismatch {
+    /// The number of nodes in the tree.
+    pub expected: usize,
+    /// The number of values given.
+    pub actual: usize,
+}
+
+impl Display for LengthMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected {} values but got {}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl Error for LengthMismatch {}
+
+/// Error returned by `Tree::validate()`. The IDs are the first node found
+/// with the problem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The node's parent comes after it.
+    InvalidParent { id: usize },
+    /// The node's stored number of descendants doesn't match the number of
+    /// nodes that have it as an ancestor.
+    WrongNumDescendants {
+        id: usize,
+        expected: usize,
+        actual: usize,
+    },
+    /// The node isn't inside its parent's range of descendants.
+    OutsideParent { id: usize },
+    /// The node's stored number of children doesn't match the number of
+    /// nodes that have it as their parent.
+    WrongNumChildren {
+        id: usize,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidParent { id } => write!(f, "node {id} has an invalid parent"),
+            Self::WrongNumDescendants {
+                id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "node {id} has {actual} descendants recorded but should have {expected}"
+            ),
+            Self::OutsideParent { id } => {
+                write!(f, "node {id} is outside its parent's descendants")
+            }
+            Self::WrongNumChildren {
+                id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "node {id} has {actual} children recorded but should have {expected}"
+            ),
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+/// Error returned by `Tree::from_indented()`. Line numbers start at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndentError {
+    /// The line is indented more than one level deeper than the line before.
+    OverIndented { line: usize },
+    /// The line's indentation isn't a whole number of indents.
+    Misaligned { line: usize },
+}
+
+impl Display for IndentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OverIndented { line } => write!(f, "line {line} is indented too far"),
+            Self::Misaligned { line } => {
+                write!(f, "line {line} is not indented by a whole number of levels")
+            }
+        }
+    }
+}
+
+impl Error for IndentError {}
+
+pub struct ParentIter<'a, K, V, I = usize> {
+    id: usize,
+    tree: &'a Tree<K, V, I>,
+}
+
+impl<'a, K, V, I: IndexType> Iterator for ParentIter<'a, K, V, I>
+where
+    K: Into<usize>,
+    usize: Into<K>,
+{
+    type Item = (K, &'a Node<K, V, I>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.tree.nodes.get(self.id).and_then(|node| {
+            if node.parent_index() == self.id {
+                None
+            } else {
+                self.id = node.parent_index();
+                self.tree
+                    .nodes
+                    .get(self.id)
+                    .map(|node| (self.id.into(), node))
+            }
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.tree.nodes.get(self.id) {
+            // Parents always come before their children so there can't be
+            // more than `id` of them.
+            Some(node) if node.parent_index() != self.id => (1, Some(self.id)),
+            _ => (0, Some(0)),
+        }
+    }
+}
+
+pub struct ChildrenIter<'a, K, V, I = usize> {
+    parent_id: usize,
+    current_id: usize,
+    max_id: usize,
+    tree: &'a Tree<K, V, I>,
+}
+
+impl<'a, K, V, I: IndexType> Iterator for ChildrenIter<'a, K, V, I>
+where
+    usize: Into<K>,
+{
+    type Item = (K, &'a Node<K, V, I>);
+
+    fn next(&mut self) -> Option<Self::Item> {
         if self.current_id <= self.max_id {
             let node = self.tree.nodes.get(self.current_id);
             let id_and_node = node.map(|node| (self.current_id.into(), node));
@@ -313,7 +2900,7 @@ where
                 .tree
                 .nodes
                 .get(self.current_id)
-                .map(|node| node.num_descendants)
+                .map(|node| node.num_descendants())
                 .unwrap_or_default()
                 + 1;
             id_and_node
@@ -321,4 +2908,105 @@ where
             None
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.current_id <= self.max_id {
+            (1, Some(self.max_id + 1 - self.current_id))
+        } else {
+            (0, Some(0))
+        }
+    }
+}
+
+impl<'a, K, V, I: IndexType> DoubleEndedIterator for ChildrenIter<'a, K, V, I>
+where
+    usize: Into<K>,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.current_id <= self.max_id {
+            // The last remaining node is in the last remaining child's subtree
+            // so walk up from it until we reach that child.
+            let mut id = self.max_id;
+            while self.tree.nodes[id].parent_index() != self.parent_id {
+                id = self.tree.nodes[id].parent_index();
+            }
+            self.max_id = id - 1;
+            Some((id.into(), &self.tree.nodes[id]))
+        } else {
+            None
+        }
+    }
+}
+
+/// An iterator over mutable references to the immediate children of a node.
+pub struct ChildrenMutIter<'a, K, V, I = usize> {
+    next_id: usize,
+    /// The nodes that haven't been visited yet, starting with the next child.
+    rest: &'a mut [Node<K, V, I>],
+}
+
+impl<'a, K, V, I: IndexType> Iterator for ChildrenMutIter<'a, K, V, I>
+where
+    usize: Into<K>,
+{
+    type Item = (K, &'a mut Node<K, V, I>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (child, rest) = mem::take(&mut self.rest).split_first_mut()?;
+        let num_descendants = child.num_descendants();
+        self.rest = &mut rest[num_descendants..];
+        let id = self.next_id;
+        self.next_id += num_descendants + 1;
+        Some((id.into(), child))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (usize::from(!self.rest.is_empty()), Some(self.rest.len()))
+    }
+}
+
+/// Build a `Tree<usize, _>` from a nested literal. Each node is a value,
+/// optionally followed by `=> { children }`, and siblings are separated by
+/// commas. Multiple top-level values produce multiple roots.
+///
+/// ```
+/// use espalier::tree;
+///
+/// let tree = tree! {
+///     0 => {
+///         1 => { 2 },
+///         3,
+///     },
+///     4,
+/// };
+/// assert_eq!(tree.len(), 5);
+/// ```
+#[macro_export]
+macro_rules! tree {
+    () => {
+        $crate::Tree::<usize, _>::new()
+    };
+    ($($nodes:tt)+) => {{
+        let mut tree = $crate::Tree::<usize, _>::new();
+        $crate::__tree_nodes!(tree; $($nodes)*);
+        tree
+    }};
+}
+
+/// Implementation detail of `tree!`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __tree_nodes {
+    ($tree:ident;) => {};
+    ($tree:ident; $value:expr => { $($children:tt)* } $(, $($rest:tt)*)?) => {
+        $tree.push($value);
+        $crate::__tree_nodes!($tree; $($children)*);
+        $tree.up();
+        $crate::__tree_nodes!($tree; $($($rest)*)?);
+    };
+    ($tree:ident; $value:expr $(, $($rest:tt)*)?) => {
+        $tree.push($value);
+        $tree.up();
+        $crate::__tree_nodes!($tree; $($($rest)*)?);
+    };
 }
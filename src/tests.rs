@@ -1,4 +1,12 @@
-use crate::Tree;
+// The library may be `no_std`, but the tests always have `std`.
+use std::prelude::rust_2021::*;
+
+use std::collections::HashSet;
+
+use crate::{
+    tree, AtTopError, BuildError, EditError, Event, IndentError, LengthMismatch, MoveError, Nested,
+    Node, NotAnEdge, PushError, Tree, TreeEdit, ValidationError,
+};
 
 // Example tree used for tests. It contains multiple roots.
 //
@@ -66,6 +74,19 @@ fn build() -> Tree<usize, i32> {
     tree
 }
 
+/// Build a chain where each node is the only child of the previous one. This
+/// sets the nodes directly because `push()` is O(depth).
+fn chain(values: impl IntoIterator<Item = i32>) -> Tree<usize, i32> {
+    let mut tree = Tree::new();
+    tree.nodes = values
+        .into_iter()
+        .enumerate()
+        .map(|(id, value)| Node::new(value, id.saturating_sub(1)))
+        .collect();
+    tree.recompute_descendant_counts();
+    tree
+}
+
 /// Basic checks on tree creation (length etc.)
 #[test]
 fn create() {
@@ -188,3 +209,2515 @@ fn up() {
     assert_eq!(tree.up(), None);
     assert_eq!(tree.up(), None);
 }
+
+/// Test lowest common ancestors.
+#[test]
+fn lca() {
+    let tree = build();
+    assert_eq!(tree.lca(5, 6), Some(3));
+    assert_eq!(tree.lca(6, 5), Some(3));
+    assert_eq!(tree.lca(9, 13), Some(7));
+    assert_eq!(tree.lca(9, 10), Some(8));
+    assert_eq!(tree.lca(4, 5), Some(4));
+    assert_eq!(tree.lca(5, 5), Some(5));
+    assert_eq!(tree.lca(2, 14), Some(0));
+    assert_eq!(tree.lca(5, 17), None);
+    assert_eq!(tree.lca(5, 19), None);
+    assert_eq!(tree.lca(19, 5), None);
+}
+
+/// Test computing aggregate ranges over subtrees.
+#[test]
+fn subtree_bounds() {
+    let tree = build();
+    let bounds = |id| {
+        tree.subtree_bounds(
+            id,
+            |&v| (v, v),
+            |(a_min, a_max), (b_min, b_max)| (a_min.min(b_min), a_max.max(b_max)),
+        )
+    };
+    assert_eq!(bounds(0), Some((0, 14)));
+    assert_eq!(bounds(7), Some((7, 13)));
+    assert_eq!(bounds(5), Some((5, 5)));
+    assert_eq!(bounds(15), Some((15, 18)));
+    assert_eq!(bounds(19), None);
+    assert_eq!(bounds(100), None);
+}
+
+/// Test paths to the root include the node itself.
+#[test]
+fn path_to_root() {
+    let tree = build();
+    assert_eq!(tree.path_to_root(9), vec![9, 8, 7, 0]);
+    assert_eq!(tree.path_to_root(17), vec![17, 16, 15]);
+    assert_eq!(tree.path_to_root(0), vec![0]);
+    assert_eq!(tree.path_to_root(19), Vec::<usize>::new());
+}
+
+/// Test prepending children.
+#[test]
+fn prepend_child() {
+    let mut tree = build();
+    assert_eq!(tree.prepend_child(0, 100), 1);
+    assert_eq!(tree.len(), 20);
+    assert!(tree
+        .children(0)
+        .map(|(_id, node)| node.value)
+        .eq([100, 1, 3, 7, 14]));
+    assert!(tree.children(2).map(|(_id, node)| node.value).eq([2]));
+    assert!(tree.children(16).map(|(_id, node)| node.value).eq([16, 18]));
+    assert!(tree.parents(6).map(|(_id, node)| node.value).eq([4, 3, 0]));
+    assert!(tree.parents(18).map(|(_id, node)| node.value).eq([16, 15]));
+    assert!(tree
+        .iter()
+        .map(|node| node.num_descendants())
+        .eq([15, 0, 1, 0, 3, 1, 0, 0, 6, 2, 0, 0, 2, 0, 0, 0, 3, 1, 0, 0]));
+
+    let mut tree = build();
+    assert_eq!(tree.prepend_child(8, 100), 9);
+    assert!(tree
+        .children(8)
+        .map(|(_id, node)| node.value)
+        .eq([100, 9, 10]));
+    assert_eq!(tree.get(0).unwrap().num_descendants(), 15);
+    assert_eq!(tree.get(16).unwrap().num_descendants(), 3);
+}
+
+/// Test node depths and distances between nodes.
+#[test]
+fn distance() {
+    let tree = build();
+    assert_eq!(tree.depth(0), 0);
+    assert_eq!(tree.depth(5), 3);
+    assert_eq!(tree.depth(15), 0);
+    assert_eq!(tree.depth(17), 2);
+
+    assert_eq!(tree.distance(5, 6), Some(3));
+    assert_eq!(tree.distance(9, 10), Some(2));
+    assert_eq!(tree.distance(9, 13), Some(4));
+    assert_eq!(tree.distance(4, 5), Some(1));
+    assert_eq!(tree.distance(5, 5), Some(0));
+    assert_eq!(tree.distance(5, 17), None);
+    assert_eq!(tree.distance(5, 19), None);
+}
+
+/// Test grouping children by parent.
+#[test]
+fn sibling_groups() {
+    let tree = build();
+    let groups: Vec<(usize, Vec<usize>)> = tree.sibling_groups().collect();
+    assert_eq!(
+        groups,
+        vec![
+            (0, vec![1, 3, 7, 14]),
+            (1, vec![2]),
+            (3, vec![4, 6]),
+            (4, vec![5]),
+            (7, vec![8, 11]),
+            (8, vec![9, 10]),
+            (11, vec![12, 13]),
+            (15, vec![16, 18]),
+            (16, vec![17]),
+        ]
+    );
+}
+
+/// Test rendering trees as ASCII art.
+#[test]
+fn display() {
+    let mut tree = Tree::<usize, i32>::new();
+    tree.push(0);
+    tree.push(1);
+    tree.up();
+    tree.push(2);
+    assert_eq!(tree.to_string(), "0\n├── 1\n└── 2\n");
+
+    assert_eq!(
+        build().to_string(),
+        "\
+0
+├── 1
+│   └── 2
+├── 3
+│   ├── 4
+│   │   └── 5
+│   └── 6
+├── 7
+│   ├── 8
+│   │   ├── 9
+│   │   └── 10
+│   └── 11
+│       ├── 12
+│       └── 13
+└── 14
+15
+├── 16
+│   └── 17
+└── 18
+"
+    );
+
+    assert_eq!(Tree::<usize, i32>::new().to_string(), "");
+}
+
+/// Test counting nodes by depth within a subtree.
+#[test]
+fn node_counts_by_relative_depth() {
+    let tree = build();
+    assert_eq!(tree.node_counts_by_relative_depth(7), vec![1, 2, 4]);
+    assert_eq!(tree.node_counts_by_relative_depth(0), vec![1, 4, 5, 5]);
+    assert_eq!(tree.node_counts_by_relative_depth(15), vec![1, 2, 1]);
+    assert_eq!(tree.node_counts_by_relative_depth(5), vec![1]);
+    assert_eq!(tree.node_counts_by_relative_depth(19), Vec::<usize>::new());
+}
+
+/// Test exporting to Graphviz.
+#[test]
+fn to_dot() {
+    let tree = build();
+    let dot = tree.to_dot(|v| v.to_string());
+    assert!(dot.starts_with("digraph {\n"));
+    assert!(dot.ends_with("}\n"));
+    assert!(dot.contains("    n5 [label=\"5\"];\n"));
+    assert!(dot.contains("    n5 -> n4;\n"));
+    assert!(dot.contains("    n4 -> n3;\n"));
+    assert!(dot.contains("    n16 -> n15;\n"));
+    assert!(!dot.contains("n0 -> n0"));
+    assert!(!dot.contains("n15 -> n15"));
+    assert_eq!(dot.matches("->").count(), 17);
+
+    let mut tree = Tree::<usize, &str>::new();
+    tree.push(r#"say "hi" \o/"#);
+    assert_eq!(
+        tree.to_dot(|v| v.to_string()),
+        "digraph {\n    n0 [label=\"say \\\"hi\\\" \\\\o/\"];\n}\n"
+    );
+}
+
+/// Test capping the size of a tree.
+#[test]
+fn trim_to_size() {
+    let mut tree = build();
+    tree.trim_to_size(100);
+    assert_eq!(tree, build());
+
+    tree.trim_to_size(10);
+    assert_eq!(tree.len(), 10);
+    assert!(tree
+        .iter()
+        .map(|node| node.num_descendants())
+        .eq([9, 1, 0, 3, 1, 0, 0, 2, 1, 0]));
+    assert!(tree.children(0).map(|(_id, node)| node.value).eq([1, 3, 7]));
+    assert!(tree.children(8).map(|(_id, node)| node.value).eq([9]));
+
+    // The stack was [15, 18] so it is now empty and we get a new root.
+    tree.push(10);
+    assert_eq!(tree.parents(10).count(), 0);
+
+    let mut tree = build();
+    tree.up();
+    tree.push(19);
+    tree.push(20);
+    tree.trim_to_size(20);
+    assert_eq!(tree.push(20), 20);
+    assert!(tree.parents(20).map(|(id, _node)| id).eq([19, 15]));
+
+    tree.trim_to_size(0);
+    assert!(tree.is_empty());
+}
+
+/// Test iterating over edges with both endpoints.
+#[test]
+fn parent_child_pairs() {
+    let tree = build();
+    let pairs: Vec<((usize, i32), (usize, i32))> = tree
+        .parent_child_pairs()
+        .map(|((parent_id, parent), (child_id, child))| {
+            ((parent_id, parent.value), (child_id, child.value))
+        })
+        .collect();
+    assert_eq!(pairs.len(), 17);
+    assert_eq!(pairs[0], ((0, 0), (1, 1)));
+    assert!(pairs.contains(&((11, 11), (13, 13))));
+    assert!(pairs.contains(&((15, 15), (18, 18))));
+    assert!(!pairs.iter().any(|&(_, (child_id, _))| child_id == 15));
+}
+
+/// Test adding children to arbitrary nodes.
+#[test]
+fn push_child() {
+    let mut tree = build();
+    assert_eq!(tree.push_child(3, 100), 7);
+    assert_eq!(tree.len(), 20);
+    assert!(tree
+        .children(3)
+        .map(|(_id, node)| node.value)
+        .eq([4, 6, 100]));
+    assert!(tree.parents(7).map(|(id, _node)| id).eq([3, 0]));
+    assert!(tree
+        .iter()
+        .map(|node| node.num_descendants())
+        .eq([15, 1, 0, 4, 1, 0, 0, 0, 6, 2, 0, 0, 2, 0, 0, 0, 3, 1, 0, 0]));
+    assert!(tree
+        .iter()
+        .map(|node| tree.get(node.parent()).unwrap().value)
+        .eq([0, 0, 1, 0, 3, 4, 3, 3, 0, 7, 8, 8, 7, 11, 11, 0, 15, 15, 16, 15]));
+
+    // The current node (18) has moved but we still add to it.
+    assert_eq!(tree.push(19), 20);
+    assert_eq!(tree.get(20).unwrap().parent(), 19);
+
+    // Add a child to a leaf at the end of a root tree.
+    let mut tree = build();
+    assert_eq!(tree.push_child(14, 100), 15);
+    assert!(tree.parents(15).map(|(id, _node)| id).eq([14, 0]));
+    assert!(tree.children(16).map(|(id, _node)| id).eq([17, 19]));
+
+    // Add a child to an ancestor of the current node. The new node goes after
+    // the current node so the ancestor becomes current.
+    let mut tree = Tree::<usize, i32>::new();
+    tree.push(0);
+    tree.push(1);
+    tree.push(2);
+    assert_eq!(tree.push_child(1, 100), 3);
+    assert_eq!(tree.current(), Some(1));
+    assert_eq!(tree.push(3), 4);
+    assert_eq!(tree.validate(), Ok(()));
+    assert!(tree.children(1).map(|(id, _node)| id).eq([2, 3, 4]));
+
+    // Add a child to a descendant of the current node. It goes at the end of
+    // the current node's subtree so the current node doesn't change.
+    let mut tree = Tree::<usize, i32>::new();
+    tree.push(0);
+    tree.push(1);
+    tree.up();
+    assert_eq!(tree.push_child(1, 2), 2);
+    assert_eq!(tree.current(), Some(0));
+    assert_eq!(tree.push(3), 3);
+    assert_eq!(tree.validate(), Ok(()));
+    assert!(tree.children(0).map(|(id, _node)| id).eq([1, 3]));
+}
+
+/// Test sorting siblings into a canonical order.
+#[test]
+fn canonicalize() {
+    let mut a = build();
+
+    // The same as the example tree but with siblings in a different order.
+    let mut b = Tree::<usize, i32>::new();
+    b.push(15);
+    b.push(18);
+    b.up();
+    b.push(16);
+    b.push(17);
+    b.up();
+    b.up();
+    b.up();
+    b.push(0);
+    b.push(14);
+    b.up();
+    b.push(7);
+    b.push(11);
+    b.push(13);
+    b.up();
+    b.push(12);
+    b.up();
+    b.up();
+    b.push(8);
+    b.push(10);
+    b.up();
+    b.push(9);
+    b.up();
+    b.up();
+    b.up();
+    b.push(3);
+    b.push(6);
+    b.up();
+    b.push(4);
+    b.push(5);
+    b.up();
+    b.up();
+    b.up();
+    b.push(1);
+    b.push(2);
+
+    assert_ne!(a, b);
+    a.canonicalize();
+    b.canonicalize();
+    assert_eq!(a, b);
+
+    // The example tree is already sorted.
+    let mut expected = build();
+    while expected.up().is_some() {}
+    assert_eq!(a, expected);
+
+    // Siblings with equal values are ordered by their children.
+    let mut c = Tree::<usize, i32>::new();
+    c.push(0);
+    c.push(1);
+    c.push(3);
+    c.up();
+    c.up();
+    c.push(1);
+    c.push(2);
+    c.canonicalize();
+    assert!(c.iter().map(|node| node.value).eq([0, 1, 2, 1, 3]));
+
+    // Very deep subtrees that only differ at the bottom don't overflow the
+    // stack.
+    let mut d = Tree::leaf(-1);
+    d.graft(
+        0,
+        Tree::concat([chain(0..100_000), chain((0..99_999).chain([-1]))]),
+    );
+    d.canonicalize();
+    assert!(d.children(0).map(|(id, _node)| id).eq([1, 100_001]));
+    assert_eq!(d.get(100_000).unwrap().value, -1);
+    assert_eq!(d.get(200_000).unwrap().value, 99_999);
+}
+
+/// Test removing subtrees.
+#[test]
+fn remove_subtree() {
+    let mut tree = build();
+    let removed = tree.remove_subtree(7);
+    assert_eq!(tree.len(), 12);
+    assert_eq!(removed.len(), 7);
+    assert!(removed.iter().map(|node| node.value).eq(7..14));
+    assert!(removed
+        .iter()
+        .map(|node| node.num_descendants())
+        .eq([6, 2, 0, 0, 2, 0, 0]));
+    assert!(removed
+        .iter()
+        .map(|node| node.parent())
+        .eq([0, 0, 1, 1, 0, 4, 4]));
+
+    assert!(tree
+        .iter()
+        .map(|node| node.value)
+        .eq([0, 1, 2, 3, 4, 5, 6, 14, 15, 16, 17, 18]));
+    assert!(tree
+        .iter()
+        .map(|node| node.num_descendants())
+        .eq([7, 1, 0, 3, 1, 0, 0, 0, 3, 1, 0, 0]));
+    assert!(tree
+        .iter()
+        .map(|node| tree.get(node.parent()).unwrap().value)
+        .eq([0, 0, 1, 0, 3, 4, 3, 0, 15, 15, 16, 15]));
+
+    // The current node is still 18 so pushing adds to it.
+    assert_eq!(tree.push(19), 12);
+    assert_eq!(tree.get(12).unwrap().parent(), 11);
+
+    // Remove a whole root tree, including the current node.
+    let mut tree = build();
+    let removed = tree.remove_subtree(15);
+    assert_eq!(tree.len(), 15);
+    assert_eq!(removed.len(), 4);
+    assert!(removed
+        .children(0)
+        .map(|(_id, node)| node.value)
+        .eq([16, 18]));
+    assert_eq!(tree.get(0).unwrap().num_descendants(), 14);
+    assert_eq!(tree.up(), None);
+
+    let mut tree = build();
+    let removed = tree.remove_subtree(0);
+    assert_eq!(removed.len(), 15);
+    assert!(tree.iter().map(|node| node.value).eq(15..19));
+    assert!(tree.iter().map(|node| node.parent()).eq([0, 0, 1, 0]));
+    assert_eq!(tree.up(), Some(0));
+}
+
+/// Test comparing subtrees across trees.
+#[test]
+fn subtree_equals() {
+    let tree = build();
+    let mut other = tree.clone();
+    assert!(tree.subtree_equals(7, &other, 7));
+    assert!(tree.subtree_equals(0, &other, 0));
+    assert!(!tree.subtree_equals(7, &other, 3));
+    assert!(!tree.subtree_equals(19, &other, 19));
+
+    // The position of the subtree doesn't matter.
+    let removed = other.remove_subtree(7);
+    assert!(tree.subtree_equals(7, &removed, 0));
+    assert!(tree.subtree_equals(15, &other, 8));
+
+    // Changing a value or the shape breaks equality.
+    let mut other = tree.clone();
+    other.get_mut(12).unwrap().value = 100;
+    assert!(!tree.subtree_equals(7, &other, 7));
+    assert!(tree.subtree_equals(3, &other, 3));
+
+    let mut other = tree.clone();
+    other.push_child(9, 100);
+    assert!(!tree.subtree_equals(7, &other, 7));
+}
+
+/// Test grafting subtrees.
+#[test]
+fn graft() {
+    let mut subtree = Tree::<usize, i32>::new();
+    subtree.push(100);
+    subtree.push(101);
+    subtree.up();
+    subtree.push(102);
+
+    let mut tree = build();
+    tree.graft(1, subtree.clone());
+    assert_eq!(tree.len(), 22);
+    assert!(tree.children(1).map(|(_id, node)| node.value).eq([2, 100]));
+    assert!(tree
+        .children(3)
+        .map(|(_id, node)| node.value)
+        .eq([101, 102]));
+    assert!(tree
+        .iter()
+        .map(|node| node.num_descendants())
+        .eq([17, 4, 0, 2, 0, 0, 3, 1, 0, 0, 6, 2, 0, 0, 2, 0, 0, 0, 3, 1, 0, 0]));
+    assert!(tree
+        .children(0)
+        .map(|(_id, node)| node.value)
+        .eq([1, 3, 7, 14]));
+    assert!(tree.parents(21).map(|(_id, node)| node.value).eq([15]));
+
+    // Grafting a multi-root tree adds all its roots as children.
+    let mut forest = subtree.clone();
+    forest.up();
+    forest.up();
+    forest.push(103);
+    let mut tree = build();
+    tree.graft(17, forest);
+    assert!(tree
+        .children(17)
+        .map(|(_id, node)| node.value)
+        .eq([100, 103]));
+    assert_eq!(tree.get(15).unwrap().num_descendants(), 7);
+
+    // Grafting then removing gets back to where we started.
+    let mut tree = build();
+    tree.graft(5, subtree.clone());
+    assert_eq!(tree.remove_subtree(6), subtree.remove_subtree(0));
+    assert_eq!(tree, build());
+
+    // Grafting under an ancestor of the current node makes it current.
+    let mut tree = Tree::<usize, i32>::new();
+    tree.push(0);
+    tree.push(1);
+    tree.graft(0, Tree::leaf(100));
+    assert_eq!(tree.current(), Some(0));
+    assert_eq!(tree.push(3), 3);
+    assert_eq!(tree.validate(), Ok(()));
+    assert!(tree
+        .children(0)
+        .map(|(_id, node)| node.value)
+        .eq([1, 100, 3]));
+
+    // Grafting under a descendant of the current node doesn't change it.
+    let mut tree = Tree::<usize, i32>::new();
+    tree.push(0);
+    tree.push(1);
+    tree.up();
+    tree.graft(1, Tree::leaf(100));
+    assert_eq!(tree.current(), Some(0));
+    assert_eq!(tree.push(3), 3);
+    assert_eq!(tree.validate(), Ok(()));
+    assert!(tree.children(0).map(|(_id, node)| node.value).eq([1, 3]));
+}
+
+/// Test inserting nodes between a parent and child.
+#[test]
+fn insert_between() {
+    let mut tree = build();
+    assert_eq!(tree.insert_between(3, 4, 100), Ok(4));
+    assert_eq!(tree.len(), 20);
+    assert!(tree.children(3).map(|(_id, node)| node.value).eq([100, 6]));
+    assert!(tree.children(4).map(|(_id, node)| node.value).eq([4]));
+    assert!(tree
+        .parents(6)
+        .map(|(_id, node)| node.value)
+        .eq([4, 100, 3, 0]));
+    assert!(tree
+        .iter()
+        .map(|node| node.num_descendants())
+        .eq([15, 1, 0, 4, 2, 1, 0, 0, 6, 2, 0, 0, 2, 0, 0, 0, 3, 1, 0, 0]));
+
+    assert_eq!(tree.insert_between(0, 5, 100), Err(NotAnEdge));
+    assert_eq!(tree.insert_between(0, 0, 100), Err(NotAnEdge));
+    assert_eq!(tree.insert_between(0, 20, 100), Err(NotAnEdge));
+    assert_eq!(tree.len(), 20);
+
+    // Wrap the current node.
+    let mut tree = build();
+    assert_eq!(tree.insert_between(15, 18, 100), Ok(18));
+    assert_eq!(tree.push(101), 20);
+    assert!(tree
+        .parents(20)
+        .map(|(_id, node)| node.value)
+        .eq([18, 100, 15]));
+    assert_eq!(tree.get(18).unwrap().num_descendants(), 2);
+}
+
+/// Test moving subtrees.
+#[test]
+fn move_subtree() {
+    // Move forwards.
+    let mut tree = build();
+    assert_eq!(tree.move_subtree(3, 11), Ok(()));
+    assert_eq!(tree.len(), 19);
+    assert!(tree
+        .children(0)
+        .map(|(_id, node)| node.value)
+        .eq([1, 7, 14]));
+    assert!(tree
+        .children(7)
+        .map(|(_id, node)| node.value)
+        .eq([12, 13, 3]));
+    assert!(tree
+        .parents(13)
+        .map(|(_id, node)| node.value)
+        .eq([3, 11, 7, 0]));
+    assert!(tree
+        .iter()
+        .map(|node| node.value)
+        .eq([0, 1, 2, 7, 8, 9, 10, 11, 12, 13, 3, 4, 5, 6, 14, 15, 16, 17, 18]));
+    assert!(tree
+        .iter()
+        .map(|node| node.num_descendants())
+        .eq([14, 1, 0, 10, 2, 0, 0, 6, 0, 0, 3, 1, 0, 0, 0, 3, 1, 0, 0]));
+
+    // Move backwards, to a different root.
+    let mut tree = build();
+    assert_eq!(tree.move_subtree(16, 1), Ok(()));
+    assert!(tree.children(1).map(|(_id, node)| node.value).eq([2, 16]));
+    assert!(tree.children(3).map(|(_id, node)| node.value).eq([17]));
+    assert_eq!(tree.get(0).unwrap().num_descendants(), 16);
+    assert!(tree.children(17).map(|(_id, node)| node.value).eq([18]));
+
+    // Cycles and invalid IDs are rejected.
+    let mut tree = build();
+    assert_eq!(tree.move_subtree(7, 9), Err(MoveError::WouldCycle));
+    assert_eq!(tree.move_subtree(7, 7), Err(MoveError::WouldCycle));
+    assert_eq!(tree.move_subtree(7, 19), Err(MoveError::InvalidId));
+    assert_eq!(tree.move_subtree(19, 7), Err(MoveError::InvalidId));
+    assert_eq!(tree, build());
+
+    // Moving to the end of an ancestor of the current node makes the ancestor
+    // current so we can keep pushing.
+    let mut tree = Tree::<usize, i32>::new();
+    tree.push(0);
+    tree.push(1);
+    tree.up();
+    tree.up();
+    tree.push(2);
+    tree.push(3);
+    assert_eq!(tree.move_subtree(1, 2), Ok(()));
+    assert_eq!(tree.current(), Some(1));
+    assert_eq!(tree.push(4), 4);
+    assert_eq!(tree.validate(), Ok(()));
+    assert!(tree.children(1).map(|(_id, node)| node.value).eq([3, 1, 4]));
+}
+
+/// Test replacing all values at once.
+#[test]
+fn set_all_values() {
+    let mut tree = build();
+    assert_eq!(tree.set_all_values(100..119), Ok(()));
+    assert!(tree.iter().map(|node| node.value).eq(100..119));
+    assert!(tree
+        .children(0)
+        .map(|(_id, node)| node.value)
+        .eq([101, 103, 107, 114]));
+
+    assert_eq!(
+        tree.set_all_values(0..18),
+        Err(LengthMismatch {
+            expected: 19,
+            actual: 18
+        })
+    );
+    assert_eq!(
+        tree.set_all_values(0..20),
+        Err(LengthMismatch {
+            expected: 19,
+            actual: 20
+        })
+    );
+    assert!(tree.iter().map(|node| node.value).eq(100..119));
+}
+
+/// Test popping nodes off the end.
+#[test]
+fn pop() {
+    let mut tree = build();
+    for value in (0..19).rev() {
+        assert_eq!(tree.pop(), Some(value));
+        assert_eq!(tree.len(), value as usize);
+    }
+    assert_eq!(tree.pop(), None);
+
+    let mut tree = build();
+    tree.pop();
+    tree.pop();
+    assert_eq!(tree.get(15).unwrap().num_descendants(), 1);
+    assert_eq!(tree.get(0).unwrap().num_descendants(), 14);
+
+    // Pushing after a pop is the same as never having pushed.
+    let mut tree = build();
+    tree.push(100);
+    tree.pop();
+    assert_eq!(tree, build());
+}
+
+/// Test clearing and truncating.
+#[test]
+fn clear_truncate() {
+    let mut tree = build();
+    tree.clear();
+    assert!(tree.is_empty());
+    assert_eq!(tree.up(), None);
+    assert_eq!(tree.push(0), 0);
+    assert_eq!(tree.get(0).unwrap().parent(), 0);
+
+    let mut tree = build();
+    tree.truncate(8);
+    assert_eq!(tree.len(), 8);
+    assert!(tree
+        .iter()
+        .map(|node| node.num_descendants())
+        .eq([7, 1, 0, 3, 1, 0, 0, 0]));
+    assert!(tree.children(0).map(|(_id, node)| node.value).eq([1, 3, 7]));
+    assert!(tree.children(7).map(|(_id, node)| node.value).eq([]));
+    assert!(tree
+        .iter()
+        .map(|node| node.parent())
+        .eq([0, 0, 1, 0, 3, 4, 3, 0]));
+
+    tree.truncate(8);
+    tree.truncate(100);
+    assert_eq!(tree.len(), 8);
+}
+
+/// Test capacity management.
+#[test]
+fn capacity() {
+    let mut tree = build();
+    tree.reserve(100);
+    assert!(tree.capacity() >= tree.len() + 100);
+
+    tree.shrink_to_fit();
+    assert!(tree.capacity() >= tree.len());
+    assert!(tree.capacity() < tree.len() + 100);
+
+    // Clearing keeps the allocation.
+    tree.clear();
+    assert!(tree.is_empty());
+    assert!(tree.capacity() > 0);
+
+    assert!(Tree::<usize, i32>::with_capacity(10).capacity() >= 10);
+}
+
+/// Test getting the current node.
+#[test]
+fn current() {
+    let mut tree = Tree::<usize, i32>::new();
+    assert_eq!(tree.current(), None);
+    assert!(tree.current_mut().is_none());
+    tree.push(0);
+    tree.push(1);
+    tree.push(2);
+    assert_eq!(tree.current(), Some(2));
+    assert_eq!(tree.last().unwrap().value, 2);
+
+    // After going up the current node is no longer the last one.
+    tree.up();
+    assert_eq!(tree.current(), Some(1));
+    assert_eq!(tree.last().unwrap().value, 2);
+    tree.current_mut().unwrap().value = 10;
+    assert_eq!(tree.get(1).unwrap().value, 10);
+
+    tree.up();
+    tree.up();
+    assert_eq!(tree.current(), None);
+    assert_eq!(tree.last().unwrap().value, 2);
+
+    let tree = build();
+    assert_eq!(tree.current(), Some(18));
+}
+
+/// Test strict up.
+#[test]
+fn try_up() {
+    let mut tree = Tree::<usize, i32>::new();
+    assert_eq!(tree.try_up(), Err(AtTopError));
+    tree.push(0);
+    tree.push(1);
+    tree.push(2);
+    assert_eq!(tree.try_up(), Ok(1));
+    assert_eq!(tree.try_up(), Ok(0));
+    assert_eq!(tree.try_up(), Err(AtTopError));
+    assert_eq!(tree.try_up(), Err(AtTopError));
+    assert_eq!(tree.current(), Some(0));
+
+    // We are still in the first root tree.
+    tree.push(3);
+    assert_eq!(tree.get(3).unwrap().parent(), 0);
+}
+
+/// Test building with closures.
+#[test]
+fn child() {
+    let mut tree = Tree::<usize, i32>::new();
+    let id = tree.child(0, |t| {
+        t.child(1, |t| {
+            t.push(2);
+        });
+        t.child(3, |t| {
+            t.child(4, |t| {
+                t.push(5);
+            });
+            t.push(6);
+        });
+    });
+    assert_eq!(id, 0);
+    assert_eq!(tree.child(15, |_| {}), 7);
+
+    let mut expected = Tree::<usize, i32>::new();
+    expected.push(0);
+    expected.push(1);
+    expected.push(2);
+    expected.up();
+    expected.up();
+    expected.push(3);
+    expected.push(4);
+    expected.push(5);
+    expected.up();
+    expected.up();
+    expected.push(6);
+    expected.up();
+    expected.up();
+    expected.up();
+    expected.push(15);
+    expected.up();
+    assert_eq!(tree, expected);
+}
+
+/// Test the `tree!` macro.
+#[test]
+fn tree_macro() {
+    let tree = tree! {
+        0 => {
+            1 => { 2 },
+            3 => {
+                4 => { 5 },
+                6,
+            },
+            7 => {
+                8 => { 9, 10 },
+                11 => { 12, 13 },
+            },
+            14,
+        },
+        15 => {
+            16 => { 17 },
+            18
+        }
+    };
+    let mut expected = build();
+    while expected.up().is_some() {}
+    assert_eq!(tree, expected);
+
+    let tree = tree! { "a" };
+    assert_eq!(tree.len(), 1);
+    assert_eq!(tree.first().unwrap().value, "a");
+
+    let tree: Tree<usize, i32> = tree! {};
+    assert!(tree.is_empty());
+}
+
+/// Test finding nodes by value.
+#[test]
+fn find() {
+    let tree = build();
+    assert_eq!(tree.find(|&v| v == 11), Some(11));
+    assert_eq!(tree.find(|&v| v > 100), None);
+    assert_eq!(tree.find(|&v| v % 5 == 4), Some(4));
+    assert!(tree.find_all(|&v| v % 5 == 4).eq([4, 9, 14]));
+    assert!(tree.find_all(|&v| v > 100).eq([]));
+}
+
+/// Test finding nodes within a subtree.
+#[test]
+fn find_in_subtree() {
+    let tree = build();
+    assert_eq!(tree.find_in_subtree(7, |&v| v == 13), Some(13));
+    assert_eq!(tree.find_in_subtree(3, |&v| v == 13), None);
+    assert_eq!(tree.find_in_subtree(7, |&v| v == 7), Some(7));
+    assert_eq!(tree.find_in_subtree(7, |&v| v == 14), None);
+    assert_eq!(tree.find_in_subtree(15, |&v| v % 2 == 0), Some(16));
+    assert_eq!(tree.find_in_subtree(19, |_| true), None);
+}
+
+/// Check children iterators work in reverse.
+#[test]
+fn children_rev() {
+    let tree = build();
+    assert!(tree
+        .children(0)
+        .rev()
+        .map(|(_id, node)| node.value)
+        .eq([14, 7, 3, 1]));
+    assert!(tree.children(7).rev().map(|(id, _node)| id).eq([11, 8]));
+    assert!(tree.children(15).rev().map(|(id, _node)| id).eq([18, 16]));
+    assert!(tree.children(2).rev().map(|(id, _node)| id).eq([]));
+    assert!(tree.children(19).rev().map(|(id, _node)| id).eq([]));
+
+    let mut children = tree.children(0).map(|(id, _node)| id);
+    assert_eq!(children.next(), Some(1));
+    assert_eq!(children.next_back(), Some(14));
+    assert_eq!(children.next_back(), Some(7));
+    assert_eq!(children.next(), Some(3));
+    assert_eq!(children.next_back(), None);
+    assert_eq!(children.next(), None);
+}
+
+/// Check iterator size hints.
+#[test]
+fn size_hint() {
+    let tree = build();
+
+    let mut parents = tree.parents(9);
+    assert_eq!(parents.size_hint(), (1, Some(9)));
+    parents.next();
+    assert_eq!(parents.size_hint(), (1, Some(8)));
+    parents.next();
+    parents.next();
+    assert_eq!(parents.size_hint(), (0, Some(0)));
+    assert_eq!(tree.parents(0).size_hint(), (0, Some(0)));
+    assert_eq!(tree.parents(19).size_hint(), (0, Some(0)));
+
+    let mut children = tree.children(0);
+    assert_eq!(children.size_hint(), (1, Some(14)));
+    children.next();
+    assert_eq!(children.size_hint(), (1, Some(12)));
+    children.next_back();
+    assert_eq!(children.size_hint(), (1, Some(11)));
+    assert_eq!(tree.children(2).size_hint(), (0, Some(0)));
+    assert_eq!(tree.children(19).size_hint(), (0, Some(0)));
+}
+
+/// Check inclusive ancestor iterators.
+#[test]
+fn ancestors_with_self() {
+    let tree = build();
+    assert!(tree
+        .ancestors_with_self(9)
+        .map(|(id, _node)| id)
+        .eq([9, 8, 7, 0]));
+    assert!(tree
+        .ancestors_with_self(9)
+        .map(|(_id, node)| node.value)
+        .eq([9, 8, 7, 0]));
+    assert!(tree.ancestors_with_self(15).map(|(id, _node)| id).eq([15]));
+    assert!(tree.ancestors_with_self(19).map(|(id, _node)| id).eq([]));
+}
+
+/// Test getting specific ancestors.
+#[test]
+fn nth_ancestor() {
+    let tree = build();
+    assert_eq!(tree.nth_ancestor(9, 0), Some(9));
+    assert_eq!(tree.nth_ancestor(9, 1), Some(8));
+    assert_eq!(tree.nth_ancestor(9, 2), Some(7));
+    assert_eq!(tree.nth_ancestor(9, 3), Some(0));
+    assert_eq!(tree.nth_ancestor(9, 4), None);
+    assert_eq!(tree.nth_ancestor(17, 2), Some(15));
+    assert_eq!(tree.nth_ancestor(19, 0), None);
+
+    assert_eq!(tree.ancestor_at_level(9, 0), Some(0));
+    assert_eq!(tree.ancestor_at_level(9, 1), Some(7));
+    assert_eq!(tree.ancestor_at_level(9, 2), Some(8));
+    assert_eq!(tree.ancestor_at_level(9, 3), Some(9));
+    assert_eq!(tree.ancestor_at_level(9, 4), None);
+    assert_eq!(tree.ancestor_at_level(18, 0), Some(15));
+    assert_eq!(tree.ancestor_at_level(19, 0), None);
+}
+
+/// Test hashing is consistent with equality.
+#[test]
+fn hash() {
+    let mut set = HashSet::new();
+    set.insert(build());
+    set.insert(build());
+    assert_eq!(set.len(), 1);
+
+    let mut other = build();
+    other.get_mut(3).unwrap().value = 100;
+    set.insert(other);
+    assert_eq!(set.len(), 2);
+
+    let mut other = build();
+    other.up();
+    set.insert(other);
+    assert_eq!(set.len(), 3);
+
+    let tree = build();
+    let nodes: HashSet<_> = tree.iter().collect();
+    assert_eq!(nodes.len(), 19);
+}
+
+/// Test comparing trees ignoring the current node.
+#[test]
+fn eq_shape() {
+    let tree = build();
+    let mut other = build();
+    other.up();
+    assert!(tree.eq_shape(&other));
+    assert_ne!(tree, other);
+
+    other.get_mut(3).unwrap().value = 100;
+    assert!(!tree.eq_shape(&other));
+
+    let mut other = build();
+    other.pop();
+    assert!(!tree.eq_shape(&other));
+}
+
+/// Test comparing tree structure ignoring values.
+#[test]
+fn equal_topology() {
+    let tree = build();
+    let mut other = Tree::<usize, String>::new();
+    for node in tree.iter() {
+        other.push(node.value.to_string());
+    }
+    assert!(!tree.equal_topology(&other));
+
+    let other = tree! {
+        "a" => {
+            "b" => { "c" },
+            "d" => { "e" => { "f" }, "g" },
+            "h" => { "i" => { "j", "k" }, "l" => { "m", "n" } },
+            "o",
+        },
+        "p" => { "q" => { "r" }, "s" },
+    };
+    assert!(tree.equal_topology(&other));
+    assert!(other.equal_topology(&tree));
+
+    let mut other = build();
+    other.pop();
+    assert!(!tree.equal_topology(&other));
+}
+
+/// Test counting nodes.
+#[test]
+fn count() {
+    let tree = build();
+    assert_eq!(tree.count_leaves(), 10);
+    assert_eq!(tree.count_where(|&v| v % 2 == 0), 10);
+    assert_eq!(tree.count_where(|&v| v > 100), 0);
+
+    let empty = Tree::<usize, i32>::new();
+    assert_eq!(empty.count_leaves(), 0);
+    assert_eq!(empty.count_where(|_| true), 0);
+}
+
+/// Test iterating over leaves.
+#[test]
+fn leaves() {
+    let tree = build();
+    assert!(tree
+        .iter_with_ids()
+        .map(|(id, node)| (id, node.value))
+        .eq((0..19).map(|i| (i, i as i32))));
+    assert!(tree
+        .leaves()
+        .map(|(_id, node)| node.value)
+        .eq([2, 5, 6, 9, 10, 12, 13, 14, 17, 18]));
+    assert!(tree
+        .leaves()
+        .map(|(id, _node)| id)
+        .eq([2, 5, 6, 9, 10, 12, 13, 14, 17, 18]));
+}
+
+/// Test iterating over internal nodes.
+#[test]
+fn internal_nodes() {
+    let tree = build();
+    assert!(tree
+        .internal_nodes()
+        .map(|(_id, node)| node.value)
+        .eq([0, 1, 3, 4, 7, 8, 11, 15, 16]));
+    assert_eq!(
+        tree.internal_nodes().count() + tree.leaves().count(),
+        tree.len()
+    );
+}
+
+/// Test counting nodes at each level.
+#[test]
+fn level_widths() {
+    let tree = build();
+    assert_eq!(tree.level_widths(), vec![2, 6, 6, 5]);
+    assert_eq!(tree.width_at_level(0), 2);
+    assert_eq!(tree.width_at_level(1), 6);
+    assert_eq!(tree.width_at_level(3), 5);
+    assert_eq!(tree.width_at_level(4), 0);
+    assert_eq!(
+        Tree::<usize, i32>::new().level_widths(),
+        Vec::<usize>::new()
+    );
+}
+
+/// Test validating trees.
+#[test]
+fn validate() {
+    assert_eq!(build().validate(), Ok(()));
+    assert_eq!(Tree::<usize, i32>::new().validate(), Ok(()));
+
+    let mut tree = build();
+    tree.nodes[5].parent = 6;
+    assert_eq!(
+        tree.validate(),
+        Err(ValidationError::InvalidParent { id: 5 })
+    );
+
+    let mut tree = build();
+    tree.nodes[8].num_descendants = 3;
+    assert_eq!(
+        tree.validate(),
+        Err(ValidationError::WrongNumDescendants {
+            id: 8,
+            expected: 2,
+            actual: 3
+        })
+    );
+
+    // The counts are right but 14 isn't next to its parent.
+    let mut tree = build();
+    tree.nodes[14].parent = 1;
+    tree.nodes[1].num_descendants = 2;
+    assert_eq!(
+        tree.validate(),
+        Err(ValidationError::OutsideParent { id: 14 })
+    );
+}
+
+/// Test recalculating descendant counts.
+#[test]
+fn recompute_descendant_counts() {
+    let mut tree = build();
+    for node in tree.nodes.iter_mut() {
+        node.num_descendants = 7;
+    }
+    assert!(tree.validate().is_err());
+    tree.recompute_descendant_counts();
+    assert_eq!(tree.validate(), Ok(()));
+    assert_eq!(tree, build());
+}
+
+/// Test using a smaller index type.
+#[test]
+fn index_type() {
+    let mut tree = Tree::<usize, i32, u32>::new();
+    tree.push(0);
+    tree.push(1);
+    tree.push(2);
+    tree.up();
+    tree.up();
+    tree.push(3);
+    assert!(tree.iter().map(|node| node.value).eq(0..4));
+    assert!(tree.children(0).map(|(id, _node)| id).eq([1, 3]));
+    assert!(tree.parents(2).map(|(id, _node)| id).eq([1, 0]));
+    assert!(tree
+        .iter()
+        .map(|node| node.num_descendants())
+        .eq([3, 1, 0, 0]));
+    assert_eq!(tree.validate(), Ok(()));
+
+    assert!(std::mem::size_of::<Node<usize, u8, u16>>() < std::mem::size_of::<Node<usize, u8>>());
+}
+
+/// Test that overflowing the index type panics.
+#[test]
+#[should_panic = "too many nodes"]
+fn index_type_overflow() {
+    let mut tree = Tree::<usize, i32, u8>::new();
+    for i in 0..257 {
+        tree.push(i);
+        tree.up();
+    }
+}
+
+/// Test reporting memory usage.
+#[test]
+fn memory_usage() {
+    let tree = build();
+    assert!(tree.memory_usage() > 0);
+    assert_eq!(
+        tree.memory_usage(),
+        tree.capacity() * std::mem::size_of::<Node<usize, i32>>()
+            + tree.parent_stack.capacity() * std::mem::size_of::<usize>()
+    );
+    assert!(tree.memory_usage() >= tree.len() * std::mem::size_of::<Node<usize, i32>>());
+    assert_eq!(Tree::<usize, i32>::new().memory_usage(), 0);
+}
+
+/// Test removing subtrees by value.
+#[test]
+fn retain_subtrees() {
+    let tree = build();
+    let filtered = tree.filter_subtrees(|&v| v != 3 && v != 8 && v != 17);
+    assert!(filtered
+        .iter()
+        .map(|node| node.value)
+        .eq([0, 1, 2, 7, 11, 12, 13, 14, 15, 16, 18]));
+    assert!(filtered.children(3).map(|(_id, node)| node.value).eq([11]));
+    assert_eq!(filtered.validate(), Ok(()));
+
+    let mut retained = tree.clone();
+    retained.retain_subtrees(|&v| v != 3 && v != 8 && v != 17);
+    assert!(retained.eq_shape(&filtered));
+    assert_eq!(retained.validate(), Ok(()));
+
+    // The current node was 18 which is kept.
+    assert_eq!(retained.current(), Some(10));
+
+    // Descendants of removed nodes aren't checked.
+    let mut checked = Vec::new();
+    let mut retained = tree.clone();
+    retained.retain_subtrees(|&v| {
+        checked.push(v);
+        v != 7
+    });
+    assert_eq!(checked, [0, 1, 2, 3, 4, 5, 6, 7, 14, 15, 16, 17, 18]);
+    assert_eq!(retained.len(), 12);
+
+    let mut retained = tree.clone();
+    retained.retain_subtrees(|&v| v != 15);
+    assert_eq!(retained.current(), None);
+    assert!(retained.eq_shape(&tree.filter_subtrees(|&v| v != 15)));
+    assert_eq!(retained.len(), 15);
+
+    let mut retained = tree.clone();
+    retained.retain_subtrees(|_| false);
+    assert!(retained.is_empty());
+}
+
+/// Test extending a tree with subtrees.
+#[test]
+fn extend() {
+    let mut tree = Tree::<usize, i32>::new();
+    tree.push(0);
+    tree.extend([tree! { 1 => { 2 } }, tree! { 3, 4 }]);
+    assert_eq!(tree.len(), 5);
+    assert!(tree.children(0).map(|(_id, node)| node.value).eq([1, 3, 4]));
+    assert!(tree
+        .iter()
+        .map(|node| node.num_descendants())
+        .eq([4, 1, 0, 0, 0]));
+
+    // We can carry on building.
+    tree.push(5);
+    assert!(tree
+        .children(0)
+        .map(|(_id, node)| node.value)
+        .eq([1, 3, 4, 5]));
+
+    // With no current node the trees become roots.
+    let mut tree = tree! { 0 };
+    tree.extend([tree! { 1 => { 2 } }, tree! { 3 }]);
+    assert!(tree.iter().map(|node| node.parent()).eq([0, 1, 1, 3]));
+}
+
+/// Test iterating over roots.
+#[test]
+fn roots() {
+    let tree = build();
+    assert!(tree.roots().map(|(id, _node)| id).eq([0, 15]));
+    assert!(Tree::<usize, i32>::new().roots().next().is_none());
+}
+
+/// Test joining trees into a forest.
+#[test]
+fn concat() {
+    let tree = Tree::concat([tree! { 0 }, tree! { 1 }, tree! { 2 }]);
+    assert_eq!(tree.len(), 3);
+    assert!(tree.roots().map(|(id, _node)| id).eq([0, 1, 2]));
+    assert!(tree.roots().map(|(_id, node)| node.value).eq([0, 1, 2]));
+
+    let mut first = build();
+    let second = first.remove_subtree(15);
+    let tree = Tree::concat([first, second]);
+    assert!(tree.eq_shape(&build()));
+    assert_eq!(tree.current(), None);
+
+    assert!(Tree::<usize, i32>::concat([]).is_empty());
+}
+
+/// Test sorting children.
+#[test]
+fn sort_children_by() {
+    let mut tree = build();
+    tree.sort_children_by(|a, b| b.cmp(a));
+    assert!(tree
+        .children(0)
+        .map(|(_id, node)| node.value)
+        .eq([14, 7, 3, 1]));
+    assert!(tree
+        .iter()
+        .map(|node| node.value)
+        .eq([0, 14, 7, 11, 13, 12, 8, 10, 9, 3, 6, 4, 5, 1, 2, 15, 18, 16, 17]));
+    assert_eq!(tree.validate(), Ok(()));
+    assert_eq!(tree.current(), None);
+
+    tree.sort_children_by(|a, b| a.cmp(b));
+    assert!(tree.eq_shape(&build()));
+}
+
+/// Test reversing children.
+#[test]
+fn reverse_children() {
+    let mut tree = build();
+    tree.reverse_children(0);
+    assert!(tree
+        .children(0)
+        .map(|(_id, node)| node.value)
+        .eq([14, 7, 3, 1]));
+    assert!(tree.children(2).map(|(_id, node)| node.value).eq([8, 11]));
+    assert!(tree
+        .iter()
+        .map(|node| node.value)
+        .eq([0, 14, 7, 8, 9, 10, 11, 12, 13, 3, 4, 5, 6, 1, 2, 15, 16, 17, 18]));
+    assert_eq!(tree.validate(), Ok(()));
+
+    tree.reverse_children(0);
+    assert!(tree.eq_shape(&build()));
+
+    // Leaves have nothing to reverse.
+    tree.reverse_children(5);
+    assert!(tree.eq_shape(&build()));
+}
+
+/// Test getting several mutable nodes at once.
+#[test]
+fn get_many_mut() {
+    let mut tree = build();
+    let [a, b] = tree.get_many_mut([3, 7]).unwrap();
+    std::mem::swap(&mut a.value, &mut b.value);
+    b.value += 100;
+    assert_eq!(tree.get(3).unwrap().value, 7);
+    assert_eq!(tree.get(7).unwrap().value, 103);
+
+    assert!(tree.get_many_mut([3, 3]).is_none());
+    assert!(tree.get_many_mut([3, 19]).is_none());
+    assert!(tree.get_many_mut::<0>([]).is_some());
+}
+
+/// Test swapping values.
+#[test]
+fn swap_values() {
+    let mut tree = build();
+    assert!(tree.swap_values(5, 13));
+    assert_eq!(tree.get(5).unwrap().value, 13);
+    assert_eq!(tree.get(13).unwrap().value, 5);
+    tree.swap_values(13, 5);
+    assert_eq!(tree, build());
+
+    assert!(tree.swap_values(5, 5));
+    assert!(!tree.swap_values(5, 19));
+    assert!(!tree.swap_values(19, 19));
+    assert_eq!(tree, build());
+}
+
+/// Test replacing values.
+#[test]
+fn replace_value() {
+    let mut tree = build();
+    assert_eq!(tree.replace_value(9, 100), Some(9));
+    assert_eq!(tree.get(9).unwrap().value, 100);
+    assert_eq!(tree.replace_value(9, 9), Some(100));
+    assert_eq!(tree.replace_value(19, 100), None);
+    assert_eq!(tree, build());
+}
+
+/// Test converting to recursive trees.
+#[test]
+fn to_nested() {
+    let leaf = |value| Nested {
+        value,
+        children: vec![],
+    };
+    let tree = build();
+    assert_eq!(
+        tree.to_nested(3),
+        Some(Nested {
+            value: 3,
+            children: vec![
+                Nested {
+                    value: 4,
+                    children: vec![leaf(5)],
+                },
+                leaf(6),
+            ],
+        })
+    );
+    assert_eq!(tree.to_nested(14), Some(leaf(14)));
+    assert_eq!(tree.to_nested(19), None);
+
+    let forest = tree.to_nested_forest();
+    assert_eq!(forest.len(), 2);
+    assert_eq!(forest[0].value, 0);
+    assert_eq!(forest[0].children.len(), 4);
+    assert_eq!(Some(&forest[1]), tree.to_nested(15).as_ref());
+}
+
+/// Test converting from recursive trees.
+#[test]
+fn from_nested() {
+    let tree = build();
+    for id in 0..tree.len() {
+        let nested = Tree::<usize, i32>::from_nested(tree.to_nested(id).unwrap());
+        assert!(nested.eq_shape(&tree.clone().remove_subtree(id)));
+    }
+
+    let forest = Tree::<usize, i32>::from_nested_forest(tree.to_nested_forest());
+    assert!(forest.eq_shape(&tree));
+    assert_eq!(forest.current(), None);
+
+    assert!(Tree::<usize, i32>::from_nested_forest(vec![]).is_empty());
+
+    // Very deep trees don't overflow the stack. This builds the `Nested` tree
+    // from the bottom up, and `from_nested()` takes it apart without dropping
+    // it recursively.
+    let mut deep = Nested {
+        value: 99_999,
+        children: Vec::new(),
+    };
+    for value in (0..99_999).rev() {
+        deep = Nested {
+            value,
+            children: vec![deep],
+        };
+    }
+    let deep = Tree::<usize, i32>::from_nested(deep);
+    assert!(deep.eq_shape(&chain(0..100_000)));
+    assert_eq!(deep.validate(), Ok(()));
+
+    // And back again, without dropping the deep `Nested` tree recursively.
+    assert!(Tree::<usize, i32>::from_nested(deep.to_nested(0).unwrap()).eq_shape(&deep));
+    let forest = Tree::<usize, i32>::from_nested_forest(deep.to_nested_forest());
+    assert!(forest.eq_shape(&deep));
+}
+
+/// Test parsing indented outlines.
+#[test]
+fn from_indented() {
+    let text = "\
+a
+  b
+    c
+
+  d
+e
+  f
+";
+    let tree = Tree::<usize, String>::from_indented(text, "  ").unwrap();
+    assert!(tree
+        .iter()
+        .map(|node| node.value.as_str())
+        .eq(["a", "b", "c", "d", "e", "f"]));
+    assert!(tree.iter().map(|node| node.parent()).eq([0, 0, 1, 0, 4, 4]));
+    assert_eq!(tree.validate(), Ok(()));
+
+    let tree = Tree::<usize, String>::from_indented("a\n\tb c \n\t\td\n", "\t").unwrap();
+    assert!(tree
+        .iter()
+        .map(|node| node.value.as_str())
+        .eq(["a", "b c ", "d"]));
+    assert!(tree.iter().map(|node| node.parent()).eq([0, 0, 1]));
+
+    assert!(Tree::<usize, String>::from_indented("", "  ")
+        .unwrap()
+        .is_empty());
+}
+
+/// Test errors parsing indented outlines.
+#[test]
+fn from_indented_errors() {
+    assert_eq!(
+        Tree::<usize, String>::from_indented("a\n  b\n      c\n", "  "),
+        Err(IndentError::OverIndented { line: 3 })
+    );
+    assert_eq!(
+        Tree::<usize, String>::from_indented("  a\n", "  "),
+        Err(IndentError::OverIndented { line: 1 })
+    );
+    assert_eq!(
+        Tree::<usize, String>::from_indented("a\n  b\n   c\n", "  "),
+        Err(IndentError::Misaligned { line: 3 })
+    );
+    assert_eq!(
+        Tree::<usize, String>::from_indented("a\n  b\n \tc\n", "  "),
+        Err(IndentError::Misaligned { line: 3 })
+    );
+}
+
+/// Test rendering indented outlines.
+#[test]
+fn to_indented() {
+    let tree = build();
+    let text = tree.to_indented("  ", |value| value.to_string());
+    assert!(text.starts_with("0\n  1\n    2\n  3\n    4\n      5\n"));
+    assert!(text.ends_with("14\n15\n  16\n    17\n  18\n"));
+
+    let parsed = Tree::<usize, String>::from_indented(&text, "  ").unwrap();
+    assert!(parsed
+        .iter()
+        .map(|node| node.value.clone())
+        .eq(tree.iter().map(|node| node.value.to_string())));
+    assert!(parsed
+        .iter()
+        .map(|node| node.parent())
+        .eq(tree.iter().map(|node| node.parent())));
+}
+
+/// Test rendering Markdown lists.
+#[test]
+fn to_markdown() {
+    let tree: Tree<usize, &str> = tree! {
+        "a" => {
+            "b" => { "c" },
+            "d",
+        },
+        "e",
+    };
+    assert_eq!(
+        tree.to_markdown(|value| value.to_string()),
+        "- a\n  - b\n    - c\n  - d\n- e\n"
+    );
+}
+
+/// Test iterating through edges.
+#[test]
+fn edges() {
+    let tree = build();
+    let edges: Vec<_> = tree.edges().collect();
+    assert_eq!(edges.len(), tree.len() - tree.roots().count());
+    assert!(edges.contains(&(3, 4)));
+    assert!(edges.contains(&(16, 17)));
+    assert!(!edges.iter().any(|&(_, child)| child == 0 || child == 15));
+}
+
+/// Test getting the adjacency list.
+#[test]
+fn adjacency_list() {
+    let tree = build();
+    let list = tree.adjacency_list();
+    assert_eq!(list.len(), tree.len());
+    assert_eq!(list[0], [1, 3, 7, 14]);
+    assert_eq!(list[4], [5]);
+    assert_eq!(list[15], [16, 18]);
+    assert!(list[2].is_empty());
+}
+
+/// Test visiting nodes mutably with their depths.
+#[test]
+fn visit_mut() {
+    let mut tree = build();
+    tree.visit_mut(|depth, value| *value = depth as i32);
+    assert!(tree
+        .iter()
+        .map(|node| node.value)
+        .eq([0, 1, 2, 1, 2, 3, 2, 1, 2, 3, 3, 2, 3, 3, 1, 0, 1, 2, 1]));
+}
+
+/// Test pruning nodes below a depth.
+#[test]
+fn prune_to_depth() {
+    let mut tree = build();
+    tree.prune_to_depth(1);
+    assert!(tree
+        .iter()
+        .map(|node| node.value)
+        .eq([0, 1, 3, 7, 14, 15, 16, 18]));
+    assert!(tree
+        .iter()
+        .map(|node| node.parent())
+        .eq([0, 0, 0, 0, 0, 5, 5, 5]));
+    assert!(tree
+        .iter()
+        .map(|node| node.num_descendants())
+        .eq([4, 0, 0, 0, 0, 2, 0, 0]));
+    assert_eq!(tree.validate(), Ok(()));
+
+    tree.prune_to_depth(0);
+    assert!(tree.iter().map(|node| node.value).eq([0, 15]));
+    assert_eq!(tree.validate(), Ok(()));
+}
+
+/// Test getting the size of subtrees.
+#[test]
+fn subtree_len() {
+    let tree = build();
+    assert_eq!(tree.subtree_len(0), 15);
+    assert_eq!(tree.subtree_len(7), 7);
+    assert_eq!(tree.subtree_len(5), 1);
+    assert_eq!(tree.subtree_len(15), 4);
+    assert_eq!(tree.subtree_len(19), 0);
+}
+
+/// Test getting the position of a node among its siblings.
+#[test]
+fn sibling_index() {
+    let tree = build();
+    assert_eq!(tree.sibling_index(1), Some(0));
+    assert_eq!(tree.sibling_index(6), Some(1));
+    assert_eq!(tree.sibling_index(14), Some(3));
+    assert_eq!(tree.sibling_index(0), Some(0));
+    assert_eq!(tree.sibling_index(15), Some(1));
+    assert_eq!(tree.sibling_index(19), None);
+}
+
+/// Test getting the nth child of a node.
+#[test]
+fn nth_child() {
+    let tree = build();
+    assert_eq!(tree.nth_child(0, 0), Some(1));
+    assert_eq!(tree.nth_child(0, 2), Some(7));
+    assert_eq!(tree.nth_child(0, 3), Some(14));
+    assert_eq!(tree.nth_child(0, 4), None);
+    assert_eq!(tree.nth_child(0, 9), None);
+    assert_eq!(tree.nth_child(15, 1), Some(18));
+    assert_eq!(tree.nth_child(5, 0), None);
+    assert_eq!(tree.nth_child(19, 0), None);
+}
+
+/// Test iterating through common ancestors.
+#[test]
+fn common_ancestors() {
+    let tree = build();
+    assert!(tree.common_ancestors(9, 13).eq([7, 0]));
+    assert!(tree.common_ancestors(5, 6).eq([3, 0]));
+    assert!(tree.common_ancestors(8, 9).eq([8, 7, 0]));
+    assert!(tree.common_ancestors(17, 18).eq([15]));
+    assert_eq!(tree.common_ancestors(2, 17).count(), 0);
+    assert_eq!(tree.common_ancestors(2, 19).count(), 0);
+}
+
+/// Test converting a tree to a different key type.
+#[test]
+fn rebase_keys() {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct NodeId(usize);
+
+    impl From<usize> for NodeId {
+        fn from(id: usize) -> Self {
+            Self(id)
+        }
+    }
+
+    impl From<NodeId> for usize {
+        fn from(id: NodeId) -> Self {
+            id.0
+        }
+    }
+
+    let tree = build();
+    let mut rebased: Tree<NodeId, i32> = tree.clone().rebase_keys();
+    assert_eq!(rebased.get(NodeId(4)).unwrap().parent(), NodeId(3));
+    assert!(rebased
+        .children(NodeId(15))
+        .map(|(id, _)| id)
+        .eq([NodeId(16), NodeId(18)]));
+    assert_eq!(rebased.current(), Some(NodeId(18)));
+    rebased.push(19);
+
+    let mut expected = tree;
+    expected.push(19);
+    assert_eq!(rebased.rebase_keys::<usize>(), expected);
+}
+
+/// Test splitting a subtree off into a new tree.
+#[test]
+fn split_off() {
+    let mut tree = build();
+    let split = tree.split_off(7);
+    assert!(split.iter().map(|node| node.value).eq(7..=13));
+    assert!(split
+        .iter()
+        .map(|node| node.parent())
+        .eq([0, 0, 1, 1, 0, 4, 4]));
+    assert_eq!(split.validate(), Ok(()));
+
+    assert!(tree
+        .iter()
+        .map(|node| node.value)
+        .eq([0, 1, 2, 3, 4, 5, 6, 14, 15, 16, 17, 18]));
+    assert_eq!(tree.get(7).unwrap().parent(), 0);
+    assert_eq!(tree.validate(), Ok(()));
+
+    let split = tree.split_off(8);
+    assert!(split.iter().map(|node| node.value).eq(15..=18));
+    assert!(tree.iter().map(|node| node.value).eq((0..=6).chain([14])));
+    assert_eq!(tree.validate(), Ok(()));
+    assert_eq!(split.validate(), Ok(()));
+}
+
+/// Test iterating in reverse.
+#[test]
+fn iter_rev() {
+    let tree = build();
+    assert!(tree.iter().rev().map(|node| node.value).eq((0..=18).rev()));
+    assert_eq!(tree.iter().len(), 19);
+}
+
+/// Test mutating the children of a node.
+#[test]
+fn children_mut() {
+    let mut tree = build();
+    for (_, child) in tree.children_mut(0) {
+        child.value *= 2;
+    }
+    assert!(tree
+        .iter()
+        .map(|node| node.value)
+        .eq([0, 2, 2, 6, 4, 5, 6, 14, 8, 9, 10, 11, 12, 13, 28, 15, 16, 17, 18]));
+    assert!(tree.children_mut(15).map(|(id, _)| id).eq([16, 18]));
+    assert_eq!(tree.children_mut(5).count(), 0);
+    assert_eq!(tree.children_mut(19).count(), 0);
+}
+
+/// Test mutating the descendants of a node.
+#[test]
+fn descendants_mut() {
+    let mut tree = build();
+    for node in tree.descendants_mut(3) {
+        node.value = -node.value;
+    }
+    assert!(tree
+        .iter()
+        .map(|node| node.value)
+        .eq([0, 1, 2, 3, -4, -5, -6].into_iter().chain(7..=18)));
+    assert!(tree.descendants_mut(5).is_empty());
+}
+
+/// Test diffing trees.
+#[test]
+fn diff() {
+    let a: Tree<usize, i32> = tree! { 0 => { 1, 2 => { 3 } } };
+    let b: Tree<usize, i32> = tree! { 0 => { 5, 2 => { 6 } } };
+    assert_eq!(
+        a.diff(&b),
+        [
+            TreeEdit::Update { id: 1, value: 5 },
+            TreeEdit::Update { id: 3, value: 6 },
+        ]
+    );
+    assert_eq!(a.diff(&a), []);
+
+    let b: Tree<usize, i32> = tree! { 0 => { 1, 2 => { 3 }, 4 => { 5 } }, 6 };
+    assert_eq!(
+        a.diff(&b),
+        [
+            TreeEdit::Insert {
+                parent: Some(0),
+                index: 2,
+                value: 4
+            },
+            TreeEdit::Insert {
+                parent: Some(4),
+                index: 0,
+                value: 5
+            },
+            TreeEdit::Insert {
+                parent: None,
+                index: 1,
+                value: 6
+            },
+        ]
+    );
+    assert_eq!(
+        b.diff(&a),
+        [TreeEdit::Remove { id: 6 }, TreeEdit::Remove { id: 4 }]
+    );
+
+    // Very deep trees don't overflow the stack.
+    let a = chain(0..100_000);
+    let b = chain((0..99_999).chain([0]));
+    assert_eq!(
+        a.diff(&b),
+        [TreeEdit::Update {
+            id: 99_999,
+            value: 0
+        }]
+    );
+    let edits = Tree::new().diff(&a);
+    assert_eq!(edits.len(), 100_000);
+    assert_eq!(
+        edits[99_999],
+        TreeEdit::Insert {
+            parent: Some(99_998),
+            index: 0,
+            value: 99_999
+        }
+    );
+    assert_eq!(a.diff(&Tree::new()), [TreeEdit::Remove { id: 0 }]);
+}
+
+/// Test applying edits from a diff.
+#[test]
+fn apply_edits() {
+    let pairs: [(Tree<usize, i32>, Tree<usize, i32>); 4] = [
+        (
+            tree! { 0 => { 1, 2 => { 3 } } },
+            tree! { 0 => { 5, 2 => { 6, 7 } }, 8 => { 9 } },
+        ),
+        (
+            tree! { 0 => { 1 => { 2, 3 }, 4 }, 5 => { 6 }, 7 },
+            tree! { 0 => { 9 }, 5 },
+        ),
+        (
+            tree! { 0 => { 1, 2 }, 3 },
+            tree! { 4 => { 5 => { 6 }, 7, 8 => { 9 } } },
+        ),
+        (tree! {}, build()),
+    ];
+    for (mut a, b) in pairs {
+        let edits = a.diff(&b);
+        a.apply_edits(&edits).unwrap();
+        assert!(a.eq_shape(&b));
+        assert_eq!(a.validate(), Ok(()));
+    }
+
+    let mut tree = build();
+    tree.apply_edits(&[TreeEdit::Insert {
+        parent: Some(0),
+        index: 0,
+        value: 20,
+    }])
+    .unwrap();
+    assert_eq!(tree.get(1).unwrap().value, 20);
+    assert!(tree.children(0).map(|(id, _)| id).eq([1, 2, 4, 8, 15]));
+    tree.apply_edits(&[TreeEdit::Insert {
+        parent: None,
+        index: 1,
+        value: 21,
+    }])
+    .unwrap();
+    assert!(tree.roots().map(|(id, _)| id).eq([0, 16, 17]));
+    assert_eq!(tree.validate(), Ok(()));
+
+    assert_eq!(
+        tree.apply_edits(&[TreeEdit::Remove { id: 21 }]),
+        Err(EditError::InvalidId)
+    );
+    assert_eq!(
+        tree.apply_edits(&[TreeEdit::Update { id: 21, value: 0 }]),
+        Err(EditError::InvalidId)
+    );
+    assert_eq!(
+        tree.apply_edits(&[TreeEdit::Insert {
+            parent: Some(21),
+            index: 0,
+            value: 0
+        }]),
+        Err(EditError::InvalidId)
+    );
+    assert_eq!(
+        tree.apply_edits(&[TreeEdit::Insert {
+            parent: Some(0),
+            index: 6,
+            value: 0
+        }]),
+        Err(EditError::InvalidIndex)
+    );
+    assert_eq!(
+        tree.apply_edits(&[TreeEdit::Insert {
+            parent: None,
+            index: 4,
+            value: 0
+        }]),
+        Err(EditError::InvalidIndex)
+    );
+
+    // Adding a root after the current node resets it, so pushing still works.
+    let mut tree = Tree::<usize, i32>::new();
+    tree.push(0);
+    tree.push(1);
+    tree.apply_edits(&[TreeEdit::Insert {
+        parent: None,
+        index: 1,
+        value: 2,
+    }])
+    .unwrap();
+    assert_eq!(tree.current(), None);
+    assert_eq!(tree.push(3), 3);
+    assert_eq!(tree.validate(), Ok(()));
+    assert!(tree.roots().map(|(_id, node)| node.value).eq([0, 2, 3]));
+}
+
+/// Test iterating with depths.
+#[test]
+fn iter_depth() {
+    let tree = build();
+    assert!(tree
+        .iter_depth()
+        .map(|(depth, _, node)| (depth, node.value))
+        .eq([0, 1, 2, 1, 2, 3, 2, 1, 2, 3, 3, 2, 3, 3, 1, 0, 1, 2, 1]
+            .into_iter()
+            .zip(0..)));
+    assert!(tree.iter_depth().map(|(_, id, _)| id).eq(0..19));
+}
+
+/// Test creating a single node tree.
+#[test]
+fn leaf() {
+    let mut tree: Tree<usize, i32> = Tree::leaf(42);
+    assert_eq!(tree.len(), 1);
+    assert_eq!(tree.first().unwrap().value, 42);
+    assert_eq!(tree.current(), Some(0));
+
+    let child = tree.push(1);
+    assert_eq!(tree.get(child).unwrap().parent(), 0);
+    assert_eq!(tree.first().unwrap().num_descendants(), 1);
+}
+
+/// Test pushing with checks for an inconsistent tree.
+#[test]
+fn push_checked() {
+    let mut tree: Tree<usize, i32> = Tree::new();
+    assert_eq!(tree.push_checked(0), Ok(0));
+    assert_eq!(tree.push_checked(1), Ok(1));
+    assert!(tree.eq_shape(&tree! { 0 => { 1 } }));
+
+    tree.parent_stack.push(5);
+    assert_eq!(tree.push_checked(2), Err(PushError::InvalidCurrent));
+    assert_eq!(tree.len(), 2);
+
+    let mut tree: Tree<usize, i32, u8> = Tree::new();
+    for value in 0..256 {
+        assert_eq!(tree.push_checked(value), Ok(value as usize));
+        tree.up();
+    }
+    assert_eq!(tree.push_checked(256), Err(PushError::TooManyNodes));
+    assert_eq!(tree.len(), 256);
+}
+
+/// Test finalizing a tree after building.
+#[test]
+fn finalize() {
+    let mut tree = build();
+    tree.finalize();
+    assert!(tree.parent_stack.is_empty());
+    assert_eq!(tree.parent_stack.capacity(), 0);
+    assert_eq!(tree.current(), None);
+    assert!(tree.eq_shape(&build()));
+    assert!(tree.iter().map(|node| node.value).eq(0..19));
+
+    let mut expected = build();
+    while expected.up().is_some() {}
+    assert_eq!(tree, expected);
+
+    tree.push(19);
+    assert_eq!(tree.roots().count(), 3);
+}
+
+/// Test building a tree with a builder.
+#[test]
+fn builder() {
+    let mut builder = Tree::builder();
+    builder.child(0, |b| {
+        b.child(1, |b| {
+            b.push(2);
+        });
+        b.child(3, |b| {
+            b.child(4, |b| {
+                b.push(5);
+            });
+            b.push(6);
+        });
+        b.child(7, |b| {
+            b.child(8, |b| {
+                b.push(9);
+                b.up();
+                b.push(10);
+            });
+            b.child(11, |b| {
+                b.push(12);
+                b.up();
+                b.push(13);
+            });
+        });
+        b.push(14);
+    });
+    builder.push(15);
+    builder.push(16);
+    builder.push(17);
+    builder.up();
+    builder.up();
+    builder.push(18);
+    let tree: Tree<usize, i32> = builder.build();
+    assert!(tree.eq_shape(&build()));
+    assert_eq!(tree.current(), None);
+}
+
+/// Test getting node values directly.
+#[test]
+fn get_value() {
+    let mut tree = build();
+    assert_eq!(tree.get_value(9), Some(&9));
+    assert_eq!(tree.get_value(100), None);
+
+    *tree.get_value_mut(9).unwrap() = 90;
+    assert_eq!(tree.get(9).unwrap().value, 90);
+    assert_eq!(tree.get_value_mut(100), None);
+}
+
+/// Test finding the root of a node.
+#[test]
+fn root_of() {
+    let tree = build();
+    assert_eq!(tree.root_of(9), Some(0));
+    assert_eq!(tree.root_of(14), Some(0));
+    assert_eq!(tree.root_of(0), Some(0));
+    assert_eq!(tree.root_of(17), Some(15));
+    assert_eq!(tree.root_of(15), Some(15));
+    assert_eq!(tree.root_of(19), None);
+}
+
+/// Test counting roots.
+#[test]
+fn num_roots() {
+    assert_eq!(build().num_roots(), 2);
+    assert_eq!(Tree::<usize, i32>::new().num_roots(), 0);
+    assert_eq!(Tree::<usize, i32>::leaf(0).num_roots(), 1);
+}
+
+/// Test extracting subtrees.
+#[test]
+fn extract_subtrees() {
+    let mut tree = build();
+    let trees = tree.extract_subtrees(|value| value % 2 == 0);
+    assert_eq!(trees.len(), 3);
+    assert!(trees[0].eq_shape(&{
+        let mut first = build();
+        first.truncate(15);
+        first
+    }));
+    assert!(trees[1].eq_shape(&tree! { 16 => { 17 } }));
+    assert!(trees[2].eq_shape(&tree! { 18 }));
+    assert!(tree.eq_shape(&tree! { 15 }));
+    assert_eq!(tree.current(), Some(0));
+    assert_eq!(tree.validate(), Ok(()));
+
+    let mut tree = build();
+    let trees = tree.extract_subtrees(|value| value % 4 == 3);
+    assert_eq!(trees.len(), 3);
+    assert!(trees[0].eq_shape(&tree! { 3 => { 4 => { 5 }, 6 } }));
+    assert!(trees[1].eq_shape(&tree! { 7 => { 8 => { 9, 10 }, 11 => { 12, 13 } } }));
+    assert!(trees[2].eq_shape(&tree! { 15 => { 16 => { 17 }, 18 } }));
+    for tree in &trees {
+        assert_eq!(tree.validate(), Ok(()));
+    }
+    assert!(tree.eq_shape(&tree! { 0 => { 1 => { 2 }, 14 } }));
+    assert_eq!(tree.current(), None);
+    assert_eq!(tree.validate(), Ok(()));
+}
+
+/// Test mapping values with their IDs.
+#[test]
+fn map_with_ids() {
+    let tree = build();
+    let mapped: Tree<usize, (usize, i32)> = tree.map_with_ids(|id, _, &value| (id, value * 10));
+    assert!(mapped.equal_topology(&tree));
+    assert!(mapped
+        .iter()
+        .enumerate()
+        .all(|(id, node)| node.value == (id, id as i32 * 10)));
+    assert_eq!(mapped.current(), tree.current());
+
+    let parents: Tree<usize, Option<usize>> = tree.map_with_ids(|_, parent, _| parent);
+    assert_eq!(parents.get_value(0), Some(&None));
+    assert_eq!(parents.get_value(4), Some(&Some(3)));
+    assert_eq!(parents.get_value(15), Some(&None));
+    assert_eq!(parents.get_value(18), Some(&Some(15)));
+}
+
+/// Test using a tree as a slice of nodes.
+#[test]
+fn as_ref() {
+    fn sum(nodes: &[Node<usize, i32>]) -> i32 {
+        nodes.iter().map(|node| node.value).sum()
+    }
+
+    let tree = build();
+    assert_eq!(sum(tree.as_ref()), (0..19).sum());
+    assert_eq!(
+        tree.as_ref().iter().position(|node| node.value == 15),
+        Some(15)
+    );
+    assert_eq!(tree.as_ref().len(), tree.len());
+}
+
+/// Test filtering nodes while keeping their ancestors.
+#[test]
+fn filter_keep_ancestors() {
+    let tree = build();
+    let filtered = tree.filter_keep_ancestors(|&value| value == 13);
+    assert!(filtered.eq_shape(&tree! { 0 => { 7 => { 11 => { 13 } } } }));
+
+    let filtered = tree.filter_keep_ancestors(|&value| value == 5 || value == 9 || value == 18);
+    assert!(filtered.eq_shape(&tree! {
+        0 => {
+            3 => { 4 => { 5 } },
+            7 => { 8 => { 9 } },
+        },
+        15 => { 18 },
+    }));
+    assert_eq!(filtered.validate(), Ok(()));
+
+    assert!(tree.filter_keep_ancestors(|_| false).is_empty());
+    assert!(tree.filter_keep_ancestors(|_| true).eq_shape(&tree));
+}
+
+/// Test building a tree from edges.
+#[test]
+fn from_edges() {
+    let tree: Tree<usize, char> = Tree::from_edges(
+        &[(2, 0), (2, 3), (0, 1), (4, 5)],
+        vec!['a', 'b', 'c', 'd', 'e', 'f'],
+        &[2, 4],
+    )
+    .unwrap();
+    assert!(tree.eq_shape(&tree! { 'c' => { 'a' => { 'b' }, 'd' }, 'e' => { 'f' } }));
+    assert_eq!(tree.validate(), Ok(()));
+
+    let values: Vec<i32> = (0..19).collect();
+    let edges: Vec<(usize, usize)> = build().edges().collect();
+    let tree: Tree<usize, i32> = Tree::from_edges(&edges, values, &[0, 15]).unwrap();
+    assert!(tree.eq_shape(&build()));
+
+    let tree: Tree<usize, i32> = Tree::from_edges(&[], vec![], &[]).unwrap();
+    assert!(tree.is_empty());
+}
+
+/// Test errors building a tree from edges.
+#[test]
+fn from_edges_errors() {
+    let from_edges = |edges: &[(usize, usize)], roots: &[usize]| {
+        Tree::<usize, i32>::from_edges(edges, vec![0; 4], roots)
+    };
+    assert_eq!(
+        from_edges(&[(0, 1), (1, 2), (2, 3), (3, 1)], &[0]),
+        Err(BuildError::MultipleParents { id: 1 })
+    );
+    assert_eq!(
+        from_edges(&[(0, 1), (2, 3), (3, 2)], &[0]),
+        Err(BuildError::Cycle { id: 2 })
+    );
+    assert_eq!(
+        from_edges(&[(0, 1), (2, 2), (2, 3)], &[0]),
+        Err(BuildError::Cycle { id: 2 })
+    );
+    assert_eq!(
+        from_edges(&[(0, 1), (0, 2)], &[0]),
+        Err(BuildError::Disconnected { id: 3 })
+    );
+    assert_eq!(
+        from_edges(&[(0, 1), (3, 2)], &[0]),
+        Err(BuildError::Disconnected { id: 3 })
+    );
+    assert_eq!(
+        from_edges(&[(0, 1), (0, 2), (2, 3)], &[0, 3]),
+        Err(BuildError::MultipleParents { id: 3 })
+    );
+    assert_eq!(
+        from_edges(&[(0, 1), (0, 4)], &[0]),
+        Err(BuildError::InvalidId { id: 4 })
+    );
+    assert_eq!(
+        from_edges(&[(0, 1), (0, 2), (0, 3)], &[5]),
+        Err(BuildError::InvalidId { id: 5 })
+    );
+}
+
+/// Test checking whether a value is in the tree.
+#[test]
+fn contains_value() {
+    let tree = build();
+    assert!(tree.contains_value(&13));
+    assert!(!tree.contains_value(&99));
+    assert!(!Tree::<usize, i32>::new().contains_value(&0));
+}
+
+/// Test finding the ID of a value.
+#[test]
+fn position_of_value() {
+    let tree = build();
+    assert_eq!(tree.position_of_value(&7), Some(7));
+    assert_eq!(tree.position_of_value(&99), None);
+
+    let tree: Tree<usize, &str> = tree! { "a" => { "b", "c" => { "b" } } };
+    assert_eq!(tree.position_of_value(&"b"), Some(1));
+    assert_eq!(tree.position_of_value(&"c"), Some(2));
+}
+
+/// Test iterating through the values of two trees together.
+#[test]
+fn zip_values() {
+    let labels: Tree<usize, &str> = tree! { "a" => { "b", "c" } };
+    let sizes: Tree<usize, i32> = tree! { 3 => { 1, 1 } };
+    assert!(labels
+        .zip_values(&sizes)
+        .unwrap()
+        .eq([(&"a", &3), (&"b", &1), (&"c", &1)]));
+
+    let sizes: Tree<usize, i32> = tree! { 3 => { 2 => { 1 } } };
+    assert!(labels.zip_values(&sizes).is_none());
+}
+
+/// Test mapping values with a fallible function.
+#[test]
+fn try_map() {
+    let tree = build();
+    let mut calls = 0;
+    let result: Result<Tree<usize, u8>, i32> = tree.try_map(|&value| {
+        calls += 1;
+        if value == 7 {
+            Err(value)
+        } else {
+            Ok(value as u8)
+        }
+    });
+    assert_eq!(result, Err(7));
+    assert_eq!(calls, 8);
+
+    let mapped: Tree<usize, String> = tree
+        .try_map(|value| Ok::<_, ()>(value.to_string()))
+        .unwrap();
+    assert!(mapped.equal_topology(&tree));
+    assert_eq!(mapped.get_value(13).unwrap(), "13");
+}
+
+/// Test the stored number of children.
+#[test]
+fn num_children() {
+    let mut tree = build();
+    assert_eq!(tree.get(0).unwrap().num_children(), 4);
+    assert!(tree
+        .iter()
+        .map(|node| node.num_children())
+        .eq([4, 1, 0, 2, 1, 0, 0, 2, 2, 0, 0, 2, 0, 0, 0, 2, 1, 0, 0]));
+
+    tree.remove_subtree(3);
+    tree.push_child(0, 19);
+    tree.insert_between(0, 1, 20).unwrap();
+    tree.truncate(tree.len() - 1);
+    assert_eq!(tree.get(0).unwrap().num_children(), 4);
+    assert_eq!(tree.get(1).unwrap().num_children(), 1);
+    assert_eq!(tree.get(tree.len() - 3).unwrap().num_children(), 1);
+    assert_eq!(tree.validate(), Ok(()));
+
+    tree.nodes[0].num_children = 2;
+    assert_eq!(
+        tree.validate(),
+        Err(ValidationError::WrongNumChildren {
+            id: 0,
+            expected: 4,
+            actual: 2
+        })
+    );
+    tree.recompute_descendant_counts();
+    assert_eq!(tree.validate(), Ok(()));
+}
+
+/// Test iterating through open and close events.
+#[test]
+fn events() {
+    let tree: Tree<usize, i32> = tree! { 0 => { 1 }, 2 };
+    let events: Vec<_> = tree
+        .events()
+        .map(|event| match event {
+            Event::Open(id, node) => (true, id, node.value),
+            Event::Close(id) => (false, id, tree.get_value(id).copied().unwrap()),
+        })
+        .collect();
+    assert_eq!(
+        events,
+        [
+            (true, 0, 0),
+            (true, 1, 1),
+            (false, 1, 1),
+            (false, 0, 0),
+            (true, 2, 2),
+            (false, 2, 2),
+        ]
+    );
+
+    let tree = build();
+    let opens = tree
+        .events()
+        .filter(|event| matches!(event, Event::Open(..)))
+        .count();
+    let closes = tree
+        .events()
+        .filter(|event| matches!(event, Event::Close(_)))
+        .count();
+    assert_eq!(opens, tree.len());
+    assert_eq!(closes, tree.len());
+    assert_eq!(
+        tree.events().next(),
+        Some(Event::Open(0, tree.get(0).unwrap()))
+    );
+    assert_eq!(tree.events().last(), Some(Event::Close(15)));
+    assert_eq!(Tree::<usize, i32>::new().events().count(), 0);
+}
+
+/// Test writing trees as JSON.
+#[cfg(feature = "std")]
+#[test]
+fn write_json() {
+    let tree: Tree<usize, i32> = tree! { 0 => { 1 => { 2 }, 3 } };
+    let mut json = Vec::new();
+    tree.write_json(&mut json, |value| value.to_string())
+        .unwrap();
+    assert_eq!(
+        json,
+        br#"{"value":0,"children":[{"value":1,"children":[{"value":2,"children":[]}]},{"value":3,"children":[]}]}"#
+    );
+
+    let tree: Tree<usize, &str> = tree! { "a", "b" => { "c" } };
+    let mut json = Vec::new();
+    tree.write_json(&mut json, |value| format!("\"{value}\""))
+        .unwrap();
+    assert_eq!(
+        json,
+        br#"[{"value":"a","children":[]},{"value":"b","children":[{"value":"c","children":[]}]}]"#
+    );
+
+    let mut json = Vec::new();
+    Tree::<usize, i32>::new()
+        .write_json(&mut json, |value| value.to_string())
+        .unwrap();
+    assert_eq!(json, b"[]");
+}
+
+/// Test reserving capacity for a graft.
+#[test]
+fn reserve_for_graft() {
+    let mut tree = build();
+    tree.shrink_to_fit();
+    let subtree = build();
+    tree.reserve_for_graft(&subtree);
+    assert!(tree.capacity() >= tree.len() + subtree.len());
+
+    let capacity = tree.capacity();
+    tree.graft(4, subtree);
+    assert_eq!(tree.capacity(), capacity);
+}
+
+/// Test copying a subtree into another tree.
+#[test]
+fn clone_subtree_into() {
+    let tree = build();
+    let mut dst = Tree::leaf(100);
+    tree.clone_subtree_into(7, &mut dst, 0);
+    assert!(dst.eq_shape(&tree! {
+        100 => {
+            7 => { 8 => { 9, 10 }, 11 => { 12, 13 } },
+        }
+    }));
+    assert_eq!(dst.validate(), Ok(()));
+
+    tree.clone_subtree_into(16, &mut dst, 2);
+    assert!(dst.eq_shape(&tree! {
+        100 => {
+            7 => { 8 => { 9, 10, 16 => { 17 } }, 11 => { 12, 13 } },
+        }
+    }));
+    assert_eq!(dst.validate(), Ok(()));
+    assert!(tree.eq_shape(&build()));
+
+    // Copying under an ancestor of the current node makes it current.
+    let mut dst = Tree::<usize, i32>::new();
+    dst.push(0);
+    dst.push(1);
+    tree.clone_subtree_into(14, &mut dst, 0);
+    assert_eq!(dst.current(), Some(0));
+    assert_eq!(dst.push(3), 3);
+    assert_eq!(dst.validate(), Ok(()));
+    assert!(dst.children(0).map(|(_id, node)| node.value).eq([1, 14, 3]));
+}
+
+/// Test trees that are locked to a single root.
+#[test]
+fn with_root() {
+    let mut tree: Tree<usize, i32> = Tree::with_root(0);
+    tree.push(1);
+    assert_eq!(tree.up(), Some(0));
+    assert_eq!(tree.up(), Some(0));
+    assert_eq!(tree.up(), Some(0));
+    tree.push(2);
+    tree.finalize();
+    tree.push(3);
+    tree.finalize();
+    tree.extend([tree! { 4 }]);
+    assert!(tree.eq_shape(&tree! { 0 => { 1, 2, 3, 4 } }));
+    assert_eq!(tree.num_roots(), 1);
+
+    // By default going up past the root starts a new root.
+    let mut tree: Tree<usize, i32> = Tree::leaf(0);
+    tree.push(1);
+    tree.up();
+    assert_eq!(tree.up(), None);
+    tree.push(2);
+    assert!(tree.eq_shape(&tree! { 0 => { 1 }, 2 }));
+    assert_eq!(tree.num_roots(), 2);
+
+    // Finalized trees are equal if their nodes are, whether or not they are
+    // locked to a single root.
+    let mut a: Tree<usize, i32> = Tree::with_root(1);
+    let mut b: Tree<usize, i32> = Tree::leaf(1);
+    a.finalize();
+    b.finalize();
+    assert_eq!(a, b);
+}
+
+/// Test removing duplicate adjacent children.
+#[test]
+fn dedup_children_by_key() {
+    let mut tree: Tree<usize, &str> = tree! {
+        "a" => {
+            "b" => { "x" },
+            "b" => { "y" },
+            "c" => { "z", "z", "w" },
+            "b",
+            "c",
+            "c" => { "v" },
+        },
+        "a",
+        "a",
+    };
+    tree.dedup_children_by_key(|value| *value);
+    assert!(tree.eq_shape(&tree! {
+        "a" => {
+            "b" => { "x" },
+            "c" => { "z", "w" },
+            "b",
+            "c",
+        },
+        "a",
+        "a",
+    }));
+    assert_eq!(tree.validate(), Ok(()));
+
+    let mut tree = build();
+    tree.dedup_children_by_key(|value| value % 2);
+    assert!(tree.eq_shape(&tree! {
+        0 => { 1 => { 2 }, 14 },
+        15 => { 16 => { 17 } },
+    }));
+}
+
+/// Test merging trees by key.
+#[test]
+fn merge_by_key() {
+    let mut tree: Tree<usize, (&str, i32)> = tree! {
+        ("cfg", 0) => {
+            ("a", 1),
+            ("b", 2) => { ("c", 3) },
+        },
+    };
+    let other: Tree<usize, (&str, i32)> = tree! {
+        ("cfg", 0) => {
+            ("b", 10) => { ("d", 30), ("c", 20) },
+            ("e", 40) => { ("f", 50) },
+        },
+    };
+    tree.merge_by_key(other, |&(name, _)| name, |a, b| a.1 += b.1);
+    assert!(tree.eq_shape(&tree! {
+        ("cfg", 0) => {
+            ("a", 1),
+            ("b", 12) => { ("c", 23), ("d", 30) },
+            ("e", 40) => { ("f", 50) },
+        },
+    }));
+    assert_eq!(tree.current(), None);
+    assert_eq!(tree.validate(), Ok(()));
+
+    // Unmatched roots are added as new roots.
+    let mut tree = build();
+    tree.merge_by_key(
+        tree! { 15 => { 19 }, 20 => { 21 } },
+        |&value| value,
+        |_, _| {},
+    );
+    let mut expected = build();
+    expected.up();
+    expected.push(19);
+    expected.up();
+    expected.up();
+    expected.push(20);
+    expected.push(21);
+    assert!(tree.eq_shape(&expected));
+    assert_eq!(tree.validate(), Ok(()));
+
+    // Very deep trees don't overflow the stack, whether they are merged or
+    // added unmatched.
+    let mut tree = chain(0..50_000);
+    tree.merge_by_key(chain(0..50_000), |&value| value, |a, b| *a += b);
+    assert!(tree.eq_shape(&chain((0..50_000).map(|value| value * 2))));
+    tree.merge_by_key(chain(1..50_001), |&value| value, |a, b| *a += b);
+    assert_eq!(tree.len(), 100_000);
+    assert!(tree.roots().map(|(id, _node)| id).eq([0, 50_000]));
+    assert_eq!(tree.get(99_999).unwrap().parent(), 99_998);
+    assert_eq!(tree.validate(), Ok(()));
+}
+
+/// Test iterating through the trees of each root.
+#[test]
+fn iter_root_subtrees() {
+    let tree = build();
+    let subtrees: Vec<_> = tree
+        .iter_root_subtrees()
+        .map(|(id, nodes)| (id, nodes.len(), nodes[0].value))
+        .collect();
+    assert_eq!(subtrees, [(0, 15, 0), (15, 4, 15)]);
+    assert_eq!(Tree::<usize, i32>::new().iter_root_subtrees().count(), 0);
+}
+
+/// Test breadth-first ordering.
+#[test]
+fn bfs_order() {
+    let tree = build();
+    assert_eq!(
+        tree.bfs_order(),
+        [0, 15, 1, 3, 7, 14, 16, 18, 2, 4, 6, 8, 11, 17, 5, 9, 10, 12, 13]
+    );
+    assert_eq!(
+        tree.preorder_to_bfs_rank(),
+        [0, 2, 8, 3, 9, 14, 10, 4, 11, 15, 16, 12, 17, 18, 5, 1, 6, 13, 7]
+    );
+    assert!(Tree::<usize, i32>::new().bfs_order().is_empty());
+}
+
+/// Test forest classification.
+#[test]
+fn is_forest() {
+    let tree = build();
+    assert!(tree.is_forest());
+    assert!(!tree.is_single_tree());
+
+    let leaf: Tree<usize, i32> = Tree::leaf(1);
+    assert!(!leaf.is_forest());
+    assert!(leaf.is_single_tree());
+
+    let empty: Tree<usize, i32> = Tree::new();
+    assert!(!empty.is_forest());
+    assert!(!empty.is_single_tree());
+}